@@ -1,25 +1,101 @@
 use pretty_hex::pretty_hex;
+use sqlite_decoder::btree;
+use sqlite_decoder::pager::FilePager;
+use sqlite_types::Pager;
 use std::env::args;
-use std::fs;
+use std::fs::File;
 
+/// Usage: decode-db [--layout] <file>
+/// With `--layout` the B-tree pages are printed as an hexdump annotated
+/// with the structure each byte belongs to.
 fn main() {
-    let filename = args().last().unwrap();
-    let contents = fs::read(filename).unwrap();
+    let mut args: Vec<String> = args().skip(1).collect();
+    let layout = args.iter().any(|arg| arg == "--layout");
+    args.retain(|arg| arg != "--layout");
+    let filename = &args[0];
+    let pager = FilePager::new(File::open(filename).unwrap()).unwrap();
+    println!("Header: {:?}", pager.header());
+    println!(
+        "Database size: {} pages ({:?})",
+        pager.page_count(),
+        pager.db_size_source()
+    );
 
-    let header = sqlite_decoder::db::decode_header(&contents).unwrap();
-    println!("Header: {:?}", header);
-
-    let db = sqlite_decoder::db::decode(&contents).unwrap();
     println!("Pages:");
-    for i in 0..db.header.db_size {
+    for i in 0..pager.header().db_size {
         // Page number are 1 indexed and 1 is the db header
         let page_number = i + 1;
 
-        if let Some(page) = db.pages.get(&page_number) {
+        if let Some(page) = pager.page(page_number).unwrap() {
             println!("page {} data {} bytes.", i, page.len());
-            println!("{}", pretty_hex(&page));
+            if layout {
+                print_layout(&pager, page_number, &page);
+            } else {
+                println!("{}", pretty_hex(&page));
+            }
         } else {
             println!("page {} data empty.", i);
         }
     }
 }
+
+fn print_layout<P: Pager>(pager: &P, page_number: u32, page: &[u8]) {
+    let layout =
+        btree::decode_page(pager, page_number).and_then(|btree| btree.layout(pager.header(), page));
+    let layout = match layout {
+        Ok(layout) => layout,
+        Err(err) => {
+            println!("not a B-tree page: {}", err);
+            println!("{}", pretty_hex(&page));
+            return;
+        }
+    };
+
+    for region in layout.regions {
+        println!(
+            "{:#06x}..{:#06x} {} ({} bytes)",
+            region.range.start,
+            region.range.end,
+            region.kind,
+            region.range.len()
+        );
+        print_hex(region.range.start, &page[region.range]);
+    }
+}
+
+/// Print 16 bytes per line prefixed by their offset in the page, repeated
+/// lines are collapsed
+fn print_hex(offset: usize, bytes: &[u8]) {
+    let mut prev: Option<&[u8]> = None;
+    let mut collapsed = false;
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if prev == Some(line) {
+            if !collapsed {
+                println!("    *");
+                collapsed = true;
+            }
+            continue;
+        }
+        prev = Some(line);
+        collapsed = false;
+
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!(
+            "    {:#06x}  {:<47}  |{}|",
+            offset + i * 16,
+            hex.join(" "),
+            ascii
+        );
+    }
+}
@@ -3,11 +3,17 @@ use std::env::args;
 use std::fs;
 
 fn main() {
-    let filename = args().last().unwrap();
+    let filename = args().next_back().unwrap();
     let contents = fs::read(filename).unwrap();
 
     let wal = sqlite_decoder::wal::decode(&contents).unwrap();
     println!("Header: {:?}", wal.header);
+    println!(
+        "Valid frames: {}, committed: {} (stopped at {})",
+        wal.frames.len(),
+        wal.committed_frames().len(),
+        wal.stop_reason
+    );
     println!("Frames:");
     for frame in wal.frames {
         println!("{:?}", frame.header);
@@ -2,6 +2,7 @@ use std::env::args;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufWriter;
 
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = args().collect();
@@ -17,11 +18,10 @@ fn main() -> std::io::Result<()> {
     first_wal.frames.extend(second_wal.frames);
     let first_wal = first_wal.rewrite_salt_1(1).rewrite_salt_2(2);
 
-    let bytes = sqlite_encoder::wal::encode(first_wal).unwrap();
-
     println!("out: ./out.wal");
-    let mut file = File::create("out.wal")?;
-    file.write_all(&bytes)?;
+    let mut file = BufWriter::new(File::create("out.wal")?);
+    sqlite_encoder::wal::encode_to(&first_wal, &mut file).unwrap();
+    file.flush()?;
 
     Ok(())
 }
@@ -14,11 +14,11 @@ fn main() -> std::io::Result<()> {
     header.page_size = new_page_size;
 
     let bytes = sqlite_encoder::db::encode_header(&header).unwrap();
-    (&mut db_contents[..100]).write(&bytes).unwrap();
+    (&mut db_contents[..100]).write_all(&bytes).unwrap();
 
     println!("out: {}.resized", db_filename);
     let mut file = File::create(format!("{}.resized", db_filename))?;
-    file.write_all(&bytes)?;
+    file.write_all(&db_contents)?;
 
     Ok(())
 }
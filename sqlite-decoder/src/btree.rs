@@ -1,36 +1,91 @@
 //! https://www.sqlite.org/fileformat.html
 
+use crate::error::{into_error, resolve_err};
 use crate::util;
 use crate::IResult;
-use crate::ParserError;
+use crate::{Error, ErrorKind, ParserError};
 use nom::bytes::complete::take;
-use sqlite_types::TextEncoding;
-
-type BoxError = Box<dyn std::error::Error>;
-
-#[derive(Debug)]
-pub enum Record {
+use sqlite_types::{DbHeader, Pager, TextEncoding};
+use std::borrow::Cow;
+use std::cmp;
+
+/// A record value, see https://www.sqlite.org/fileformat.html#record_format
+/// Blobs and text borrow from the page when possible.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record<'a> {
     Null,
     Int8(i8),
     Int16(i16),
-    Blob(Vec<u8>),
-    Text(String),
+    Int24(i32),
+    Int32(i32),
+    Int48(i64),
+    Int64(i64),
+    Float64(f64),
+    /// Serial type 8, the integer 0
+    Zero,
+    /// Serial type 9, the integer 1
+    One,
+    Blob(Cow<'a, [u8]>),
+    Text(Cow<'a, str>),
 }
 
-impl Record {
-    pub fn as_string(&self) -> String {
+impl<'a> Record<'a> {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+
+    /// Returns the value of any of the integer serial types
+    pub fn as_int(&self) -> Option<i64> {
         match self {
-            Self::Text(v) => v.clone(),
-            Self::Null => "NULL".to_owned(),
-            v => unreachable!("expected string, given {:?}", v),
+            Self::Int8(v) => Some(*v as i64),
+            Self::Int16(v) => Some(*v as i64),
+            Self::Int24(v) => Some(*v as i64),
+            Self::Int32(v) => Some(*v as i64),
+            Self::Int48(v) => Some(*v),
+            Self::Int64(v) => Some(*v),
+            Self::Zero => Some(0),
+            Self::One => Some(1),
+            _ => None,
         }
     }
 
-    pub fn as_int(&self) -> usize {
+    pub fn as_float(&self) -> Option<f64> {
         match self {
-            Self::Int8(v) => *v as usize,
-            Self::Int16(v) => *v as usize,
-            _ => unreachable!(),
+            Self::Float64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Text(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            Self::Blob(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Detach the record from the page it was decoded from
+    pub fn into_owned(self) -> Record<'static> {
+        use Record::*;
+        match self {
+            Null => Null,
+            Int8(v) => Int8(v),
+            Int16(v) => Int16(v),
+            Int24(v) => Int24(v),
+            Int32(v) => Int32(v),
+            Int48(v) => Int48(v),
+            Int64(v) => Int64(v),
+            Float64(v) => Float64(v),
+            Zero => Zero,
+            One => One,
+            Blob(v) => Blob(Cow::Owned(v.into_owned())),
+            Text(v) => Text(Cow::Owned(v.into_owned())),
         }
     }
 }
@@ -38,24 +93,24 @@ impl Record {
 #[derive(Clone)]
 pub struct InputContext<'a> {
     pub(crate) input: &'a [u8],
-    pub(crate) original_input: Vec<u8>,
+    pub(crate) original_input: &'a [u8],
 }
 
 #[derive(Debug)]
-pub enum Cell {
+pub enum Cell<'a> {
     // FIXME: Remove BTree in those names
-    TableBTreeLeafCell(TableBTreeLeafCell),
+    TableBTreeLeafCell(TableBTreeLeafCell<'a>),
     TableBTreeInteriorCell(TableBTreeInteriorCell),
-    IndexBTreeLeafCell(IndexBTreeLeafCell),
-    IndexBTreeInteriorCell(IndexBTreeInteriorCell),
+    IndexBTreeLeafCell(IndexBTreeLeafCell<'a>),
+    IndexBTreeInteriorCell(IndexBTreeInteriorCell<'a>),
 }
 
 #[derive(Debug)]
-pub struct TableBTreeLeafCell {
+pub struct TableBTreeLeafCell<'a> {
     /// A varint which is the integer key, a.k.a. "rowid"
-    rowid: u64,
-    pub records: Vec<Record>,
-    page_first_overflow: Option<u32>,
+    pub rowid: u64,
+    pub payload: RecordView<'a>,
+    pub page_first_overflow: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -65,40 +120,87 @@ pub struct TableBTreeInteriorCell {
 }
 
 #[derive(Debug)]
-pub struct IndexBTreeLeafCell {}
+pub struct IndexBTreeLeafCell<'a> {
+    /// The key of the index, followed by the rowid of the row it points to
+    pub payload: RecordView<'a>,
+    pub page_first_overflow: Option<u32>,
+}
 
 #[derive(Debug)]
-pub struct IndexBTreeInteriorCell {}
+pub struct IndexBTreeInteriorCell<'a> {
+    pub left_child_page: u32,
+    /// The key of the index, followed by the rowid of the row it points to
+    pub payload: RecordView<'a>,
+    pub page_first_overflow: Option<u32>,
+}
 
 #[derive(Debug)]
 pub struct BtreeHeader {
-    page_type: PageType,
-    start_first_freeblock: u16,
-    cell_count: u16,
-    start_cell_content_area: u16,
-    fragmented_free_bytes_count: u8,
-    right_most_pointer: Option<u32>,
+    pub page_type: PageType,
+    pub start_first_freeblock: u16,
+    pub cell_count: u16,
+    pub start_cell_content_area: u16,
+    pub fragmented_free_bytes_count: u8,
+    pub right_most_pointer: Option<u32>,
 }
 
 #[derive(Debug)]
-pub struct Btree {
+pub struct Btree<'a> {
+    /// Offset of the B-Tree header in the page, 100 on the first page
+    pub header_offset: usize,
     pub header: BtreeHeader,
-    pub cells: Vec<Cell>,
+    pub cells: Vec<Cell<'a>>,
+}
+
+impl<'a> Btree<'a> {
+    /// Detach the B-Tree from the page it was decoded from
+    pub fn into_owned(self) -> Btree<'static> {
+        Btree {
+            header_offset: self.header_offset,
+            header: self.header,
+            cells: self.cells.into_iter().map(Cell::into_owned).collect(),
+        }
+    }
+}
+
+impl<'a> Cell<'a> {
+    /// Detach the cell from the page it was decoded from
+    pub fn into_owned(self) -> Cell<'static> {
+        match self {
+            Cell::TableBTreeLeafCell(cell) => Cell::TableBTreeLeafCell(TableBTreeLeafCell {
+                rowid: cell.rowid,
+                payload: cell.payload.into_owned(),
+                page_first_overflow: cell.page_first_overflow,
+            }),
+            Cell::TableBTreeInteriorCell(cell) => Cell::TableBTreeInteriorCell(cell),
+            Cell::IndexBTreeLeafCell(cell) => Cell::IndexBTreeLeafCell(IndexBTreeLeafCell {
+                payload: cell.payload.into_owned(),
+                page_first_overflow: cell.page_first_overflow,
+            }),
+            Cell::IndexBTreeInteriorCell(cell) => {
+                Cell::IndexBTreeInteriorCell(IndexBTreeInteriorCell {
+                    left_child_page: cell.left_child_page,
+                    payload: cell.payload.into_owned(),
+                    page_first_overflow: cell.page_first_overflow,
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-enum PageContent {
+pub enum PageContent {
     Index,
     Table,
 }
 
 #[derive(Debug)]
-enum PageType {
+pub enum PageType {
     Leaf(PageContent),
     Interior(PageContent),
 }
 impl PageType {
-    fn header_size(&self) -> u8 {
+    pub fn header_size(&self) -> u8 {
         use PageType::*;
         match self {
             Leaf(_) => 8,
@@ -106,24 +208,20 @@ impl PageType {
         }
     }
 
-    fn is_interior(&self) -> bool {
+    pub fn is_interior(&self) -> bool {
         matches!(self, PageType::Interior(_))
     }
 }
 
 fn decode_page_type<'a>(input: InputContext<'a>) -> IResult<InputContext<'a>, PageType> {
+    let start = input.input;
     let (input, byte) = input.read_u8()?;
     let t = match byte {
         0x02 => PageType::Interior(PageContent::Index),
         0x05 => PageType::Interior(PageContent::Table),
         0x0a => PageType::Leaf(PageContent::Index),
         0x0d => PageType::Leaf(PageContent::Table),
-        e => {
-            return Err(nom::Err::Failure(ParserError(format!(
-                "unsupported page type: {}",
-                e
-            ))))
-        }
+        e => return Err(ParserError::failure(ErrorKind::BadPageType(e), start)),
     };
     Ok((input, t))
 }
@@ -168,25 +266,27 @@ fn decode_cell_pointers<'a>(
     Ok((input, cell_pointers))
 }
 
-fn decode_cell<'a>(
-    enc: &TextEncoding,
+fn decode_cell<'a, P: Pager + ?Sized>(
+    pager: &P,
     parent: &PageType,
     input: InputContext<'a>,
-) -> IResult<InputContext<'a>, Cell> {
+) -> IResult<InputContext<'a>, Cell<'a>> {
     let (input, cell) = match parent {
         PageType::Leaf(PageContent::Table) => {
-            let (input, cell) = decode_table_leaf_cell(enc, input)?;
+            let (input, cell) = decode_table_leaf_cell(pager, parent, input)?;
             (input, Cell::TableBTreeLeafCell(cell))
         }
         PageType::Interior(PageContent::Table) => {
             let (input, cell) = decode_table_interior_cell(input)?;
             (input, Cell::TableBTreeInteriorCell(cell))
         }
-        e => {
-            return Err(nom::Err::Failure(ParserError(format!(
-                "unsupported cell with parent: {:?}",
-                e
-            ))))
+        PageType::Leaf(PageContent::Index) => {
+            let (input, cell) = decode_index_leaf_cell(pager, parent, input)?;
+            (input, Cell::IndexBTreeLeafCell(cell))
+        }
+        PageType::Interior(PageContent::Index) => {
+            let (input, cell) = decode_index_interior_cell(pager, parent, input)?;
+            (input, Cell::IndexBTreeInteriorCell(cell))
         }
     };
 
@@ -208,78 +308,316 @@ fn decode_table_interior_cell<'a>(
     ))
 }
 
-fn decode_table_leaf_cell<'a>(
-    enc: &TextEncoding,
+fn decode_table_leaf_cell<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page_type: &PageType,
     input: InputContext<'a>,
-) -> IResult<InputContext<'a>, TableBTreeLeafCell> {
+) -> IResult<InputContext<'a>, TableBTreeLeafCell<'a>> {
     let (input, total_payload_size) = input.read_varint()?;
     let (input, rowid) = input.read_varint()?;
-    // FIXME: using the total_payload_size to read only the part that doesn't
-    // overflow?
-    let (input, raw_payload) = input.read_bytes(total_payload_size as usize)?;
-    let (_input, records) = decode_records(enc, raw_payload)?;
-
-    // FIXME: implement overflow handling. How to detect if it overflowed?
-    // FIXME: it's written in the spec
-    // let (input, page_first_overflow) = input.read_u32()?;
-    let page_first_overflow = None;
+    let (input, (payload, page_first_overflow)) =
+        decode_payload(pager, page_type, total_payload_size, input)?;
+    let payload = decode_record_view(&pager.header().text_encoding, payload)?;
 
     Ok((
         input,
         TableBTreeLeafCell {
             rowid,
-            records,
+            payload,
             page_first_overflow,
         },
     ))
 }
 
-fn decode_records<'a>(enc: &TextEncoding, input: &'a [u8]) -> IResult<&'a [u8], Vec<Record>> {
-    let (input, (header_size, took)) = read_varint(input)?;
+fn decode_index_leaf_cell<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page_type: &PageType,
+    input: InputContext<'a>,
+) -> IResult<InputContext<'a>, IndexBTreeLeafCell<'a>> {
+    let (input, total_payload_size) = input.read_varint()?;
+    let (input, (payload, page_first_overflow)) =
+        decode_payload(pager, page_type, total_payload_size, input)?;
+    let payload = decode_record_view(&pager.header().text_encoding, payload)?;
 
-    // Header without the header size varint
-    let header_input = &input[..header_size as usize - took];
-    let (_input, columns) = decode_record_columns(header_input)?;
+    Ok((
+        input,
+        IndexBTreeLeafCell {
+            payload,
+            page_first_overflow,
+        },
+    ))
+}
 
-    let mut input = &input[header_size as usize - took..];
+fn decode_index_interior_cell<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page_type: &PageType,
+    input: InputContext<'a>,
+) -> IResult<InputContext<'a>, IndexBTreeInteriorCell<'a>> {
+    let (input, left_child_page) = input.read_u32()?;
+    let (input, total_payload_size) = input.read_varint()?;
+    let (input, (payload, page_first_overflow)) =
+        decode_payload(pager, page_type, total_payload_size, input)?;
+    let payload = decode_record_view(&pager.header().text_encoding, payload)?;
 
-    let records = {
-        let mut values = Vec::with_capacity(columns.len());
+    Ok((
+        input,
+        IndexBTreeInteriorCell {
+            left_child_page,
+            payload,
+            page_first_overflow,
+        },
+    ))
+}
+
+/// Returns the number of bytes of a payload stored on the B-tree page itself,
+/// see `DbHeader::local_payload_size`
+pub fn local_payload_size(
+    header: &DbHeader,
+    page_type: &PageType,
+    payload_size: u64,
+) -> Result<usize, ErrorKind> {
+    let table_leaf = matches!(page_type, PageType::Leaf(PageContent::Table));
+    header
+        .local_payload_size(table_leaf, payload_size)
+        .ok_or(ErrorKind::BadPayloadFractions)
+}
+
+/// Reads the payload of a cell, following the overflow page chain if the
+/// payload doesn't fit on the page.
+/// Returns the complete payload and the first overflow page, if any.
+/// The payload is only copied when it overflowed.
+fn decode_payload<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page_type: &PageType,
+    payload_size: u64,
+    input: InputContext<'a>,
+) -> IResult<InputContext<'a>, (Cow<'a, [u8]>, Option<u32>)> {
+    let local_size = local_payload_size(pager.header(), page_type, payload_size)
+        .map_err(|kind| nom::Err::Failure(ParserError::without_position(kind)))?;
+    let (input, local_payload) = input.read_bytes(local_size)?;
+
+    if local_size as u64 == payload_size {
+        return Ok((input, (Cow::Borrowed(local_payload), None)));
+    }
 
-        for serial_type in columns {
-            let res = decode_record_value(enc, serial_type, input)?;
-            input = res.0;
-            values.push(res.1);
+    let (input, page_first_overflow) = input.read_u32()?;
+    let payload = read_overflow_pages(pager, page_first_overflow, local_payload, payload_size)?;
+
+    Ok((input, (Cow::Owned(payload), Some(page_first_overflow))))
+}
+
+fn read_overflow_pages<P: Pager + ?Sized>(
+    pager: &P,
+    page_first_overflow: u32,
+    local_payload: &[u8],
+    payload_size: u64,
+) -> Result<Vec<u8>, nom::Err<ParserError>> {
+    let usable_size = pager.header().usable_size() as usize;
+    if usable_size <= 4 {
+        return Err(nom::Err::Failure(ParserError::without_position(
+            ErrorKind::BadOverflowChain,
+        )));
+    }
+
+    // A valid chain visits each page at most once, which bounds the payload
+    // of corrupted cells.
+    let page_count = pager.page_count() as usize;
+    let max_payload_size = local_payload.len() as u64 + page_count as u64 * usable_size as u64;
+    let mut payload = Vec::with_capacity(cmp::min(payload_size, max_payload_size) as usize);
+    payload.extend_from_slice(local_payload);
+
+    let mut visited = 0;
+    let mut next_page = page_first_overflow;
+    while (payload.len() as u64) < payload_size {
+        visited += 1;
+        if next_page == 0 || visited > page_count {
+            return Err(nom::Err::Failure(ParserError::without_position(
+                ErrorKind::BadOverflowChain,
+            )));
         }
 
-        values
-    };
+        let page = pager
+            .page(next_page)
+            .map_err(|err| {
+                nom::Err::Failure(ParserError::without_position(ErrorKind::Io(
+                    err.to_string(),
+                )))
+            })?
+            .ok_or_else(|| {
+                nom::Err::Failure(ParserError::without_position(ErrorKind::MissingPage(
+                    next_page,
+                )))
+            })?;
+
+        // Overflow pages start with the next page number followed by
+        // the content.
+        let (content, page_number) =
+            util::read_u32(&page).map_err(|err| resolve_err(err, Some(next_page), &page))?;
+        let remaining = (payload_size - payload.len() as u64) as usize;
+        let (_, content) = take(cmp::min(remaining, usable_size - 4))(content)
+            .map_err(|err| resolve_err(err, Some(next_page), &page))?;
+        payload.extend_from_slice(content);
+
+        next_page = page_number;
+    }
 
-    Ok((input, records))
+    Ok(payload)
 }
 
-fn decode_record_columns<'a>(mut input: &'a [u8]) -> IResult<&'a [u8], Vec<u64>> {
-    let mut columns = Vec::new();
-    loop {
-        let res = read_varint(input)?;
-        input = res.0;
-        columns.push(res.1 .0);
+/// Lazy view over a record: the record header is parsed once and each
+/// column is decoded on demand.
+/// See https://www.sqlite.org/fileformat.html#record_format
+#[derive(Debug, Clone)]
+pub struct RecordView<'a> {
+    enc: TextEncoding,
+    payload: Cow<'a, [u8]>,
+    columns: Vec<RecordColumn>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordColumn {
+    pub serial_type: u64,
+    /// Offset of the value from the start of the payload
+    pub offset: usize,
+    /// Size of the value in bytes
+    pub size: usize,
+}
+
+impl<'a> RecordView<'a> {
+    /// Number of columns in the record
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn columns(&self) -> &[RecordColumn] {
+        &self.columns
+    }
+
+    /// The complete payload, header included
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Decode the value of column `n`
+    /// Values borrow from the page the record was decoded from. Records
+    /// spilled to overflow pages were assembled in an owned buffer, their
+    /// text and blobs are copied.
+    /// Error offsets are relative to the start of the payload.
+    pub fn get(&self, n: usize) -> Result<Record<'a>, Error> {
+        let column = self
+            .columns
+            .get(n)
+            .ok_or_else(|| Error::new(ErrorKind::ColumnOutOfBounds(n)))?;
+
+        match &self.payload {
+            Cow::Borrowed(payload) => decode_column(&self.enc, payload, column),
+            Cow::Owned(payload) => {
+                decode_column(&self.enc, payload, column).map(Record::into_owned)
+            }
+        }
+    }
 
-        if input.is_empty() {
-            break;
+    /// Detach the record from the page it was decoded from
+    pub fn into_owned(self) -> RecordView<'static> {
+        RecordView {
+            enc: self.enc,
+            payload: Cow::Owned(self.payload.into_owned()),
+            columns: self.columns,
         }
     }
 
-    Ok((input, columns.to_owned()))
+    /// Decode the values of all columns
+    pub fn records(&self) -> Result<Vec<Record<'a>>, Error> {
+        (0..self.len()).map(|n| self.get(n)).collect()
+    }
+}
+
+fn decode_column<'a>(
+    enc: &TextEncoding,
+    payload: &'a [u8],
+    column: &RecordColumn,
+) -> Result<Record<'a>, Error> {
+    let input = &payload[column.offset..column.offset + column.size];
+    decode_record_value(enc, column.serial_type, input)
+        .map(|(_, record)| record)
+        .map_err(|err| into_error(err, None, payload))
+}
+
+/// Parse the header of a record
+pub fn decode_record<'a>(enc: &TextEncoding, payload: &'a [u8]) -> Result<RecordView<'a>, Error> {
+    decode_record_view(enc, Cow::Borrowed(payload)).map_err(|err| into_error(err, None, payload))
+}
+
+fn decode_record_view<'a>(
+    enc: &TextEncoding,
+    payload: Cow<'a, [u8]>,
+) -> Result<RecordView<'a>, nom::Err<ParserError>> {
+    let (input, (header_size, took)) = read_varint(&payload)?;
+
+    // Header without the header size varint
+    let header_size = (header_size as usize)
+        .checked_sub(took)
+        .ok_or_else(|| ParserError::failure(ErrorKind::BadRecordHeader, &payload))?;
+    let (_, mut header_input) = take::<_, _, ParserError>(header_size)(input)
+        .map_err(|_| ParserError::failure(ErrorKind::BadRecordHeader, &payload))?;
+
+    let mut columns = Vec::new();
+    let mut offset = took + header_size;
+    while !header_input.is_empty() {
+        let (input, (serial_type, _)) = read_varint(header_input)?;
+
+        let size = serial_type_size(serial_type).ok_or_else(|| {
+            ParserError::failure(ErrorKind::BadSerialType(serial_type), header_input)
+        })?;
+        header_input = input;
+        columns.push(RecordColumn {
+            serial_type,
+            offset,
+            size,
+        });
+        offset = offset
+            .checked_add(size)
+            .ok_or_else(|| ParserError::failure(ErrorKind::BadRecordHeader, &payload))?;
+    }
+
+    if offset > payload.len() {
+        return Err(ParserError::failure(ErrorKind::BadRecordHeader, &payload));
+    }
+
+    Ok(RecordView {
+        enc: enc.clone(),
+        payload,
+        columns,
+    })
+}
+
+/// Size in bytes of a value of the given serial type
+pub fn serial_type_size(serial_type: u64) -> Option<usize> {
+    let size = match serial_type {
+        0 | 8 | 9 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        5 => 6,
+        6 | 7 => 8,
+        10 | 11 => return None,
+        v => (v as usize - 12) / 2,
+    };
+    Some(size)
 }
 
 fn decode_record_value<'a>(
     enc: &TextEncoding,
     serial_type: u64,
     input: &'a [u8],
-) -> IResult<&'a [u8], Record> {
+) -> IResult<&'a [u8], Record<'a>> {
     use Record::*;
-    let (input, serial_type) = match serial_type {
+    let (input, record) = match serial_type {
         0 => (input, Null),
         1 => {
             let (input, value) = take(1usize)(input)?;
@@ -289,68 +627,150 @@ fn decode_record_value<'a>(
             let (input, value) = take(2usize)(input)?;
             (input, Int16(i16::from_be_bytes(value.try_into().unwrap())))
         }
-        v if v > 12 && v % 2 == 0 => {
+        3 => {
+            let (input, value) = take(3usize)(input)?;
+            (input, Int24(read_be_int(value) as i32))
+        }
+        4 => {
+            let (input, value) = take(4usize)(input)?;
+            (input, Int32(i32::from_be_bytes(value.try_into().unwrap())))
+        }
+        5 => {
+            let (input, value) = take(6usize)(input)?;
+            (input, Int48(read_be_int(value)))
+        }
+        6 => {
+            let (input, value) = take(8usize)(input)?;
+            (input, Int64(i64::from_be_bytes(value.try_into().unwrap())))
+        }
+        7 => {
+            let (input, value) = take(8usize)(input)?;
+            (
+                input,
+                Float64(f64::from_be_bytes(value.try_into().unwrap())),
+            )
+        }
+        8 => (input, Zero),
+        9 => (input, One),
+        v if v >= 12 && v % 2 == 0 => {
             let size = (v as usize - 12) / 2;
             let (input, bytes) = take(size)(input)?;
 
-            (input, Blob(bytes.to_owned()))
+            (input, Blob(Cow::Borrowed(bytes)))
         }
-        v if v > 13 && v % 2 != 0 => {
+        v if v >= 13 && v % 2 != 0 => {
             let size = (v as usize - 13) / 2;
 
             let (input, bytes) = take(size)(input)?;
-
-            use TextEncoding::*;
-            let bytes = bytes.to_vec();
-            let value = match enc {
-                UTF8 => String::from_utf8(bytes).unwrap(),
-                _ => unimplemented!(),
-            };
+            let value = decode_text(enc, bytes)?;
 
             (input, Text(value))
         }
-        e => {
-            return Err(nom::Err::Failure(ParserError(format!(
-                "unsupported serial type: {}",
-                e
-            ))))
-        }
+        e => return Err(ParserError::failure(ErrorKind::BadSerialType(e), input)),
     };
 
-    Ok((input, serial_type))
+    Ok((input, record))
 }
 
-/// Decode the B-Tree on the first page
-pub fn decode_first_page<'a>(enc: &'a TextEncoding, page: &'a [u8]) -> Result<Btree, BoxError> {
-    // first 100 of the first page are for the database header but preserve the
-    // original input for the absolute offset seek.
-    let input = &page[100..];
-    let input = InputContext {
-        input,
-        original_input: page.to_owned(),
+/// Decode text in the database text encoding
+/// UTF-8 text is borrowed, UTF-16 text needs to be converted.
+fn decode_text<'a>(
+    enc: &TextEncoding,
+    bytes: &'a [u8],
+) -> Result<Cow<'a, str>, nom::Err<ParserError>> {
+    use TextEncoding::*;
+    let value = match enc {
+        // SQLite defaults to UTF-8 when the encoding hasn't been set yet
+        Unspecified | UTF8 => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+        UTF16le | UTF16be => {
+            if !bytes.len().is_multiple_of(2) {
+                None
+            } else {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| match enc {
+                        UTF16le => u16::from_le_bytes([c[0], c[1]]),
+                        _ => u16::from_be_bytes([c[0], c[1]]),
+                    })
+                    .collect();
+                String::from_utf16(&units).ok().map(Cow::Owned)
+            }
+        }
     };
-    match decode_btree(enc, input) {
-        Ok((_, btree)) => Ok(btree),
-        Err(err) => Err(format!("failed to decode: {}", err).into()),
+
+    value.ok_or_else(|| ParserError::failure(ErrorKind::BadText, bytes))
+}
+
+/// Sign-extend a big-endian two's complement integer of up to 8 bytes
+fn read_be_int(bytes: &[u8]) -> i64 {
+    let mut v = if bytes[0] & 0x80 != 0 { -1i64 } else { 0i64 };
+    for b in bytes {
+        v = (v << 8) | *b as i64;
     }
+    v
+}
+
+/// Decode the B-Tree on the first page
+pub fn decode_first_page<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page: &'a [u8],
+) -> Result<Btree<'a>, Error> {
+    decode_btree_at(pager, page, 100).map_err(|err| into_error(err, Some(1), page))
 }
 
 /// Decode the B-Tree on a page
-pub fn decode<'a>(enc: &'a TextEncoding, input: &'a [u8]) -> Result<Btree, BoxError> {
+/// The pager is used to follow overflow pages.
+pub fn decode<'a, P: Pager + ?Sized>(pager: &P, input: &'a [u8]) -> Result<Btree<'a>, Error> {
+    decode_btree_at(pager, input, 0).map_err(|err| into_error(err, None, input))
+}
+
+/// Decode the B-Tree on page `page_number` of the database
+/// The B-Tree borrows from the pager when it keeps the page in memory,
+/// otherwise its records are copied out of the page.
+pub fn decode_page<P: Pager + ?Sized>(pager: &P, page_number: u32) -> Result<Btree<'_>, Error> {
+    if pager.header().is_ptrmap_page(page_number) {
+        return Err(Error::new(ErrorKind::PtrmapPage(page_number)));
+    }
+
+    let page = pager
+        .page(page_number)
+        .map_err(|err| Error::from(err).with_page(page_number))?
+        .ok_or_else(|| Error::new(ErrorKind::MissingPage(page_number)))?;
+
+    // The first page starts with the database header
+    let offset = if page_number == 1 { 100 } else { 0 };
+    match page {
+        Cow::Borrowed(page) => decode_btree_at(pager, page, offset)
+            .map_err(|err| into_error(err, Some(page_number), page)),
+        Cow::Owned(page) => decode_btree_at(pager, &page, offset)
+            .map(Btree::into_owned)
+            .map_err(|err| into_error(err, Some(page_number), &page)),
+    }
+}
+
+/// Decode the B-Tree starting at `offset` in the page, but preserve the
+/// original input for the absolute offset seek.
+fn decode_btree_at<'a, P: Pager + ?Sized>(
+    pager: &P,
+    page: &'a [u8],
+    offset: usize,
+) -> Result<Btree<'a>, nom::Err<ParserError>> {
+    let input = page
+        .get(offset..)
+        .ok_or_else(|| ParserError::failure(ErrorKind::Truncated, page))?;
     let input = InputContext {
         input,
-        original_input: input.to_owned(),
+        original_input: page,
     };
-    match decode_btree(enc, input) {
-        Ok((_, btree)) => Ok(btree),
-        Err(err) => Err(format!("failed to decode: {}", err).into()),
-    }
+    let (_, btree) = decode_btree(pager, input)?;
+    Ok(btree)
 }
 
-fn decode_btree<'a>(
-    enc: &TextEncoding,
+fn decode_btree<'a, P: Pager + ?Sized>(
+    pager: &P,
     input: InputContext<'a>,
-) -> IResult<InputContext<'a>, Btree> {
+) -> IResult<InputContext<'a>, Btree<'a>> {
+    let header_offset = input.original_input.len() - input.input.len();
     let (input, header) = decode_header(input)?;
     let (input, cell_pointers) = decode_cell_pointers(&header, input)?;
     let (input, cells) = {
@@ -359,9 +779,9 @@ fn decode_btree<'a>(
         let prev_input = input.clone();
 
         for cell_pointer in cell_pointers {
-            let input = input.seek_at(cell_pointer as usize);
+            let input = input.seek_at(cell_pointer as usize)?;
 
-            let res = decode_cell(enc, &header.page_type, input)?;
+            let res = decode_cell(pager, &header.page_type, input)?;
             cells.push(res.1);
         }
 
@@ -370,21 +790,28 @@ fn decode_btree<'a>(
 
     // FIXME: consume the input ??? the cell are always at the end of the page...
 
-    let btree = Btree { header, cells };
+    let btree = Btree {
+        header_offset,
+        header,
+        cells,
+    };
     Ok((input, btree))
 }
 
 impl<'a> InputContext<'a> {
-    fn seek_at(&'a self, offset: usize) -> InputContext<'a> {
-        let input = &self.original_input[offset..];
-        Self {
+    fn seek_at(&self, offset: usize) -> Result<InputContext<'a>, nom::Err<ParserError>> {
+        let input = self
+            .original_input
+            .get(offset..)
+            .ok_or_else(|| ParserError::failure(ErrorKind::BadCellPointer(offset), self.input))?;
+        Ok(Self {
             input,
-            original_input: self.original_input.clone(),
-        }
+            original_input: self.original_input,
+        })
     }
 
     fn read_u32(self) -> IResult<InputContext<'a>, u32> {
-        let (input, v) = util::read_u32(&self.input)?;
+        let (input, v) = util::read_u32(self.input)?;
         Ok((
             Self {
                 input,
@@ -395,7 +822,7 @@ impl<'a> InputContext<'a> {
     }
 
     fn read_u16(self) -> IResult<InputContext<'a>, u16> {
-        let (input, v) = util::read_u16(&self.input)?;
+        let (input, v) = util::read_u16(self.input)?;
         Ok((
             Self {
                 input,
@@ -406,7 +833,7 @@ impl<'a> InputContext<'a> {
     }
 
     fn read_u8(self) -> IResult<InputContext<'a>, u8> {
-        let (input, v) = util::read_u8(&self.input)?;
+        let (input, v) = util::read_u8(self.input)?;
         Ok((
             Self {
                 input,
@@ -417,7 +844,7 @@ impl<'a> InputContext<'a> {
     }
 
     fn read_varint(self) -> IResult<InputContext<'a>, u64> {
-        let (input, (v, _)) = read_varint(&self.input)?;
+        let (input, (v, _)) = read_varint(self.input)?;
 
         Ok((
             Self {
@@ -442,25 +869,339 @@ impl<'a> InputContext<'a> {
 }
 
 /// Returns (value, variable size)
-fn read_varint<'a>(input: &'a [u8]) -> IResult<&'a [u8], (u64, usize)> {
+pub(crate) fn read_varint(input: &[u8]) -> IResult<&[u8], (u64, usize)> {
     let mut v = 0u64;
-    let mut i = 0usize;
 
-    loop {
-        if i >= 8 {
-            break;
-        }
+    for i in 0..8 {
+        let byte = *input
+            .get(i)
+            .ok_or_else(|| ParserError::failure(ErrorKind::BadVarint, input))?;
 
-        v = (v << 7) + (input[i] & 0x7f) as u64;
-        if (input[i] & 0x80) == 0 {
+        v = (v << 7) + (byte & 0x7f) as u64;
+        if (byte & 0x80) == 0 {
             return Ok((&input[i + 1..], (v, i + 1)));
         }
+    }
 
-        i += 1;
+    // The ninth byte contributes all of its 8 bits
+    let byte = *input
+        .get(8)
+        .ok_or_else(|| ParserError::failure(ErrorKind::BadVarint, input))?;
+    v = (v << 8) + byte as u64;
+
+    Ok((&input[9..], (v, 9)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlite_types::Db;
+    use test_support::{create_db, create_db_bytes_with_reserved_bytes};
+
+    #[test]
+    fn it_decodes_all_serial_types() {
+        let mut input = vec![14, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 13, 17];
+        input.extend([0xff]);
+        input.extend([0x01, 0x00]);
+        input.extend([0xff, 0xff, 0xfe]);
+        input.extend([0x00, 0x01, 0x00, 0x00]);
+        input.extend([0x80, 0, 0, 0, 0, 0]);
+        input.extend(i64::MAX.to_be_bytes());
+        input.extend(1.5f64.to_be_bytes());
+        input.extend(b"hi");
+
+        let view = decode_record(&TextEncoding::UTF8, &input).unwrap();
+        let records = view.records().unwrap();
+
+        use Record::*;
+        assert_eq!(
+            records,
+            vec![
+                Null,
+                Int8(-1),
+                Int16(256),
+                Int24(-2),
+                Int32(65536),
+                Int48(-(1 << 47)),
+                Int64(i64::MAX),
+                Float64(1.5),
+                Zero,
+                One,
+                Blob(Cow::Borrowed(&[])),
+                Text(Cow::Borrowed("")),
+                Text(Cow::Borrowed("hi")),
+            ]
+        );
+
+        let ints: Vec<Option<i64>> = records.iter().map(|r| r.as_int()).collect();
+        assert_eq!(
+            ints,
+            vec![
+                None,
+                Some(-1),
+                Some(256),
+                Some(-2),
+                Some(65536),
+                Some(-(1 << 47)),
+                Some(i64::MAX),
+                None,
+                Some(0),
+                Some(1),
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(records[7].as_float(), Some(1.5));
+        assert_eq!(records[12].as_str(), Some("hi"));
+        assert_eq!(records[10].as_blob(), Some(&[][..]));
+        assert!(records[0].is_null());
+    }
+
+    #[test]
+    fn it_rejects_reserved_serial_types() {
+        let input = [2, 10];
+        assert!(decode_record(&TextEncoding::UTF8, &input).is_err());
+    }
+
+    #[test]
+    fn it_reassembles_overflow_pages() {
+        let db = create_db(
+            "
+            create table test (id integer primary key, value text);
+            insert into test values (1, 'small');
+            insert into test values (2, printf('%.*c', 10000, 'a'));
+            insert into test values (3, printf('%.*c', 5000, 'b'));
+            ",
+        );
+
+        let btree = decode(&db, &db.pages[&2]).unwrap();
+        let cells: Vec<&TableBTreeLeafCell> = btree
+            .cells
+            .iter()
+            .map(|cell| match cell {
+                Cell::TableBTreeLeafCell(cell) => cell,
+                cell => panic!("unexpected cell: {:?}", cell),
+            })
+            .collect();
+        assert_eq!(cells.len(), 3);
+
+        assert_eq!(cells[0].payload.get(1).unwrap().as_str(), Some("small"));
+        assert!(cells[0].page_first_overflow.is_none());
+        // Records that fit on the page borrow from it
+        assert!(matches!(
+            cells[0].payload.get(1).unwrap(),
+            Record::Text(Cow::Borrowed(_))
+        ));
+
+        assert_eq!(cells[1].rowid, 2);
+        assert_eq!(
+            cells[1].payload.get(1).unwrap().as_str(),
+            Some("a".repeat(10000).as_str())
+        );
+        assert!(cells[1].page_first_overflow.is_some());
+
+        assert_eq!(
+            cells[2].payload.get(1).unwrap().as_str(),
+            Some("b".repeat(5000).as_str())
+        );
+        assert!(cells[2].page_first_overflow.is_some());
     }
 
-    v = (v << 8) + (input[i] & 0xff) as u64;
+    #[test]
+    fn it_walks_index_btree() {
+        let db = create_db(
+            "
+            pragma page_size = 1024;
+            create table test (id integer primary key, value text);
+            create index test_value on test (value);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 500)
+            insert into test select i, printf('value %04d %.*c', i, i % 300 + 1, 'x') from n;
+            ",
+        );
+
+        fn walk(db: &Db, page_number: u32, keys: &mut Vec<(String, i64)>) {
+            let btree = decode(db, &db.pages[&page_number]).unwrap();
+            for cell in btree.cells {
+                match cell {
+                    Cell::IndexBTreeInteriorCell(cell) => {
+                        walk(db, cell.left_child_page, keys);
+                        keys.push((
+                            cell.payload.get(0).unwrap().as_str().unwrap().to_owned(),
+                            cell.payload.get(1).unwrap().as_int().unwrap(),
+                        ));
+                    }
+                    Cell::IndexBTreeLeafCell(cell) => {
+                        keys.push((
+                            cell.payload.get(0).unwrap().as_str().unwrap().to_owned(),
+                            cell.payload.get(1).unwrap().as_int().unwrap(),
+                        ));
+                    }
+                    cell => panic!("unexpected cell: {:?}", cell),
+                }
+            }
+            if let Some(right_most_pointer) = btree.header.right_most_pointer {
+                walk(db, right_most_pointer, keys);
+            }
+        }
+
+        // The index was created after the table, on page 3
+        let mut keys = Vec::new();
+        walk(&db, 3, &mut keys);
 
-    let input = &input[9..];
-    Ok((input, (v, 9)))
+        assert_eq!(keys.len(), 500);
+        for (i, (value, rowid)) in keys.iter().enumerate() {
+            let i = i as i64 + 1;
+            assert_eq!(*rowid, i);
+            assert!(value.starts_with(&format!("value {:04} ", i)));
+            assert_eq!(value.len(), 12 + (i as usize % 300));
+        }
+    }
+
+    #[test]
+    fn it_decodes_utf16_text() {
+        for encoding in ["UTF-16le", "UTF-16be"] {
+            let db = create_db(&format!(
+                "
+                pragma encoding = '{}';
+                create table test (value text);
+                insert into test values ('héllo wörld 🦀');
+                ",
+                encoding
+            ));
+
+            let btree = decode(&db, &db.pages[&2]).unwrap();
+            match &btree.cells[0] {
+                Cell::TableBTreeLeafCell(cell) => {
+                    assert_eq!(
+                        cell.payload.get(0).unwrap().as_str(),
+                        Some("héllo wörld 🦀")
+                    )
+                }
+                cell => panic!("unexpected cell: {:?}", cell),
+            }
+        }
+    }
+
+    #[test]
+    fn it_exposes_record_columns() {
+        let input = [4, 1, 23, 0, 42, b'h', b'e', b'l', b'l', b'o'];
+        let view = decode_record(&TextEncoding::UTF8, &input).unwrap();
+
+        assert_eq!(view.len(), 3);
+        assert_eq!(
+            view.columns(),
+            &[
+                RecordColumn {
+                    serial_type: 1,
+                    offset: 4,
+                    size: 1
+                },
+                RecordColumn {
+                    serial_type: 23,
+                    offset: 5,
+                    size: 5
+                },
+                RecordColumn {
+                    serial_type: 0,
+                    offset: 10,
+                    size: 0
+                },
+            ]
+        );
+        assert_eq!(view.get(1).unwrap().as_str(), Some("hello"));
+        assert_eq!(view.get(0).unwrap().as_int(), Some(42));
+        assert!(view.get(2).unwrap().is_null());
+        assert!(view.get(3).is_err());
+
+        // Values borrow from the payload and outlive the view
+        let value = decode_record(&TextEncoding::UTF8, &input)
+            .unwrap()
+            .get(1)
+            .unwrap();
+        assert_eq!(value, Record::Text(Cow::Borrowed("hello")));
+    }
+
+    #[test]
+    fn it_rejects_truncated_record() {
+        let input = [3, 1, 23, 42, b'h'];
+        assert!(decode_record(&TextEncoding::UTF8, &input).is_err());
+    }
+
+    #[test]
+    fn it_honours_reserved_bytes() {
+        let bytes = create_db_bytes_with_reserved_bytes(
+            "
+            pragma page_size = 1024;
+            pragma auto_vacuum = full;
+            create table test (id integer primary key, value text);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 200)
+            insert into test select i, printf('%.*c', i * 17, 'x') from n;
+            ",
+            40,
+        );
+        let db = crate::db::decode(&bytes).unwrap();
+        assert_eq!(db.header.reserved_bytes, 40);
+        assert_eq!(db.header.usable_size(), 984);
+
+        let mut values = Vec::new();
+        fn walk(db: &Db, page_number: u32, values: &mut Vec<(u64, usize)>) {
+            let btree = decode_page(db, page_number).unwrap();
+            for cell in btree.cells {
+                match cell {
+                    Cell::TableBTreeInteriorCell(cell) => walk(db, cell.left_child_page, values),
+                    Cell::TableBTreeLeafCell(cell) => {
+                        let value = cell.payload.get(1).unwrap();
+                        assert!(value.as_str().unwrap().bytes().all(|b| b == b'x'));
+                        values.push((cell.rowid, value.as_str().unwrap().len()));
+                    }
+                    cell => panic!("unexpected cell: {:?}", cell),
+                }
+            }
+            if let Some(right_most_pointer) = btree.header.right_most_pointer {
+                walk(db, right_most_pointer, values);
+            }
+        }
+        walk(&db, 3, &mut values);
+
+        assert_eq!(values.len(), 200);
+        for (i, (rowid, len)) in values.into_iter().enumerate() {
+            assert_eq!(rowid, i as u64 + 1);
+            assert_eq!(len, (i + 1) * 17);
+        }
+
+        // Pointer-map entries are spread according to the usable size
+        assert_eq!(
+            crate::ptrmap::compute(&db).unwrap(),
+            crate::ptrmap::decode(&db).unwrap()
+        );
+
+        let layout = decode_page(&db, 3)
+            .unwrap()
+            .layout(&db.header, &db.pages[&3])
+            .unwrap();
+        let last = layout.regions.last().unwrap();
+        assert_eq!(last.range, 984..1024);
+        assert_eq!(last.kind, crate::layout::RegionKind::Reserved);
+    }
+
+    #[test]
+    fn it_reports_error_location() {
+        let mut db = create_db("create table test (value text);");
+
+        // Corrupt the page type of the table root page
+        db.pages.get_mut(&2).unwrap()[0] = 0x42;
+        let err = decode_page(&db, 2).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadPageType(0x42));
+        assert_eq!(err.page, Some(2));
+        assert_eq!(err.offset, Some(0));
+
+        let err = decode_page(&db, 42).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingPage(42));
+
+        // Varint running past the end of the record
+        let err = decode_record(&TextEncoding::UTF8, &[2, 0x81]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadVarint);
+        assert_eq!(err.offset, Some(1));
+    }
 }
@@ -1,25 +1,40 @@
 //! https://www.sqlite.org/fileformat.html
+use crate::error::into_error;
 use crate::util::{read_u16, read_u32, read_u8};
 use crate::IResult;
-use crate::ParserError;
+use crate::{Error, ErrorKind, ParserError};
 use nom::bytes::complete::take;
-use sqlite_types::{Db, DbHeader, TextEncoding, MAGIC_STRING};
+use sqlite_types::{Db, DbHeader, DbSizeSource, TextEncoding, MAGIC_STRING};
+use std::cmp;
 use std::collections::HashMap;
 
-type BoxError = Box<dyn std::error::Error>;
+pub fn decode(input: &[u8]) -> Result<Db, Error> {
+    decode_with_size_source(input).map(|(db, _)| db)
+}
 
-pub fn decode<'a>(input: &'a [u8]) -> Result<Db, BoxError> {
+/// Same as `decode`, also returning where `header.db_size` was taken from
+pub fn decode_with_size_source(input: &[u8]) -> Result<(Db, DbSizeSource), Error> {
     match decode_db(input) {
         Ok((_, db)) => Ok(db),
-        Err(err) => Err(format!("failed to decode: {}", err).into()),
+        Err(err) => {
+            let mut err = into_error(err, None, input);
+
+            // Locate the error within its page
+            if let (Ok(header), Some(offset)) = (decode_header(input), err.offset) {
+                err.page = Some((offset / header.page_size as usize) as u32 + 1);
+                err.offset = Some(offset % header.page_size as usize);
+            }
+
+            Err(err)
+        }
     }
 }
 
-fn decode_db<'a, 'b>(input: &'a [u8]) -> IResult<&'a [u8], Db> {
+fn decode_db(input: &[u8]) -> IResult<&[u8], (Db, DbSizeSource)> {
     let mut pages = HashMap::new();
 
     let (input, input_header) = take(100usize)(input)?;
-    let (_, header) = decode_header_inner(&input_header)?;
+    let (_, mut header) = decode_header_inner(input_header)?;
 
     // Eat align to page size and discard the bytes
     let (input, bytes) = take(header.page_size - 100)(input)?;
@@ -28,37 +43,35 @@ fn decode_db<'a, 'b>(input: &'a [u8]) -> IResult<&'a [u8], Db> {
     let first_page = [input_header, bytes].concat();
     pages.insert(1, first_page);
 
-    // The remaining bytes should be pages and the number should match the
-    // db_size in the header
-    assert_eq!(
-        input.len(),
-        header.page_size as usize * (header.db_size as usize - 1)
-    );
-
-    let page_count = input.len() / header.page_size as usize;
+    // The in-header size is only used when valid, otherwise the remaining
+    // bytes are pages
+    let file_page_count = input.len() / header.page_size as usize + 1;
+    let (db_size, db_size_source) =
+        header.resolve_db_size(cmp::min(file_page_count, u32::MAX as usize) as u32);
+    header.db_size = db_size;
 
     let mut input = input;
-    for i in 1..=page_count {
+    for i in 1..db_size {
         let ret = take(header.page_size)(input)?;
         input = ret.0;
 
         // Page number are 1 indexed and 1 is the db header
         let page_number = i + 1;
-        pages.insert(page_number as u32, ret.1.to_owned());
+        pages.insert(page_number, ret.1.to_owned());
     }
 
-    assert_eq!(pages.len(), header.db_size as usize);
-    Ok((input, Db { header, pages }))
+    Ok((input, (Db { header, pages }, db_size_source)))
 }
 
-pub fn decode_header(input: &[u8]) -> Result<DbHeader, BoxError> {
+pub fn decode_header(input: &[u8]) -> Result<DbHeader, Error> {
     match decode_header_inner(input) {
         Ok((_, header)) => Ok(header),
-        Err(err) => Err(format!("failed to decode: {}", err).into()),
+        Err(err) => Err(into_error(err, Some(1), input)),
     }
 }
 
 fn decode_text_encoding(input: &[u8]) -> IResult<&[u8], TextEncoding> {
+    let start = input;
     let (input, t) = read_u32(input)?;
 
     use TextEncoding::*;
@@ -68,10 +81,10 @@ fn decode_text_encoding(input: &[u8]) -> IResult<&[u8], TextEncoding> {
         2 => UTF16le,
         3 => UTF16be,
         e => {
-            return Err(nom::Err::Failure(ParserError(format!(
-                "unsupported text encoding: {}",
-                e
-            ))))
+            return Err(ParserError::failure(
+                ErrorKind::UnsupportedTextEncoding(e),
+                start,
+            ))
         }
     };
 
@@ -79,18 +92,18 @@ fn decode_text_encoding(input: &[u8]) -> IResult<&[u8], TextEncoding> {
 }
 
 fn decode_header_inner(input: &[u8]) -> IResult<&[u8], DbHeader> {
+    let start = input;
     let (input, magic_string) = take(16usize)(input)?;
     if magic_string != MAGIC_STRING {
-        return Err(nom::Err::Failure(ParserError(format!(
-            "magic string not found, got: {:?}",
-            magic_string
-        ))));
+        return Err(ParserError::failure(ErrorKind::BadMagic, start));
     }
 
+    let page_size_input = input;
     let (input, page_size) = read_u16(input)?;
     let (input, file_format_write_version) = read_u8(input)?;
     let (input, file_format_read_version) = read_u8(input)?;
-    let (input, _reserved) = take(1usize)(input)?;
+    let reserved_bytes_input = input;
+    let (input, reserved_bytes) = read_u8(input)?;
     let (input, max_embedded_payload_frac) = read_u8(input)?;
     let (input, min_embedded_payload_frac) = read_u8(input)?;
     let (input, leaf_payload_frac) = read_u8(input)?;
@@ -115,6 +128,20 @@ fn decode_header_inner(input: &[u8]) -> IResult<&[u8], DbHeader> {
     } else {
         page_size as u32
     };
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+        return Err(ParserError::failure(
+            ErrorKind::BadPageSize(page_size),
+            page_size_input,
+        ));
+    }
+
+    // The usable size is not allowed to be less than 480
+    if page_size - (reserved_bytes as u32) < 480 {
+        return Err(ParserError::failure(
+            ErrorKind::BadReservedBytes(reserved_bytes),
+            reserved_bytes_input,
+        ));
+    }
 
     Ok((
         input,
@@ -122,6 +149,7 @@ fn decode_header_inner(input: &[u8]) -> IResult<&[u8], DbHeader> {
             page_size,
             file_format_write_version,
             file_format_read_version,
+            reserved_bytes,
             max_embedded_payload_frac,
             min_embedded_payload_frac,
             leaf_payload_frac,
@@ -142,3 +170,80 @@ fn decode_header_inner(input: &[u8]) -> IResult<&[u8], DbHeader> {
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pager::BufferPager;
+    use sqlite_types::Pager;
+    use test_support::create_db_bytes;
+
+    #[test]
+    fn it_reports_bad_magic() {
+        let err = decode(&[0u8; 512]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadMagic);
+        assert_eq!(err.offset, Some(0));
+    }
+
+    #[test]
+    fn it_applies_the_in_header_size_rule() {
+        let mut bytes = create_db_bytes(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            ",
+        );
+        let (db, db_size_source) = decode_with_size_source(&bytes).unwrap();
+        let db_size = db.header.db_size;
+        assert_eq!(db_size as usize, bytes.len() / 512);
+        assert_eq!(db_size_source, DbSizeSource::Header);
+
+        // Trailing bytes are ignored when the in-header size is valid
+        bytes.extend_from_slice(&[0u8; 1024 + 10]);
+        let (db, db_size_source) = decode_with_size_source(&bytes).unwrap();
+        assert_eq!(db.header.db_size, db_size);
+        assert_eq!(db.pages.len(), db_size as usize);
+        assert_eq!(db_size_source, DbSizeSource::Header);
+
+        // Written by SQLite before 3.7.0, the size comes from the file
+        bytes[92..96].copy_from_slice(&0u32.to_be_bytes());
+        let (db, db_size_source) = decode_with_size_source(&bytes).unwrap();
+        assert_eq!(db.header.db_size, db_size + 2);
+        assert_eq!(db.pages.len(), db_size as usize + 2);
+        assert_eq!(
+            db_size_source,
+            DbSizeSource::FileLength {
+                header_db_size: db_size
+            }
+        );
+        let pager = BufferPager::new(&bytes).unwrap();
+        assert_eq!(pager.page_count(), db_size + 2);
+        assert_eq!(pager.db_size_source(), db_size_source);
+    }
+
+    #[test]
+    fn it_decodes_partially_copied_file() {
+        let bytes = create_db_bytes(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            ",
+        );
+        let db_size = decode(&bytes).unwrap().header.db_size;
+
+        let bytes = &bytes[..2 * 512 + 100];
+        let (db, db_size_source) = decode_with_size_source(bytes).unwrap();
+        assert_eq!(db.header.db_size, 2);
+        assert_eq!(
+            db_size_source,
+            DbSizeSource::FileLength {
+                header_db_size: db_size
+            }
+        );
+        let pager = BufferPager::new(bytes).unwrap();
+        assert_eq!(pager.page_count(), 2);
+        assert!(pager.page(3).unwrap().is_none());
+    }
+}
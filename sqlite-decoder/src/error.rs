@@ -0,0 +1,230 @@
+use std::fmt;
+
+/// Error returned by the decoders, with the location of the problem in the
+/// input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Page number (1 indexed) where the error occurred, when known
+    pub page: Option<u32>,
+    /// Byte offset where the error occurred, when known.
+    /// Relative to the start of the page when `page` is set, otherwise to the
+    /// start of the input.
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// Input ended before the structure was complete
+    Truncated,
+    /// Database magic string or WAL magic number not found
+    BadMagic,
+    UnsupportedFileFormat(u32),
+    UnsupportedTextEncoding(u32),
+    /// Page size that isn't a power of two between 512 and 65536
+    BadPageSize(u32),
+    /// Reserved bytes leaving less than 480 usable bytes per page
+    BadReservedBytes(u8),
+    /// Payload fractions leaving no room for the payload in the cells
+    BadPayloadFractions,
+    BadPageType(u8),
+    /// Cell pointer beyond the end of the page
+    BadCellPointer(usize),
+    /// Freeblock beyond the end of the page, out of order or too small
+    BadFreeblock(usize),
+    /// Cells or freeblocks overlapping each other or the page headers
+    OverlappingContent(usize),
+    /// Varint running past the end of the input
+    BadVarint,
+    BadSerialType(u64),
+    /// Record header size inconsistent with the payload
+    BadRecordHeader,
+    /// Text that isn't valid in the database text encoding
+    BadText,
+    /// Overflow page chain ending before the payload was complete
+    BadOverflowChain,
+    /// Page referenced by the file but not present in the database
+    MissingPage(u32),
+    /// Column index out of bounds of a record
+    ColumnOutOfBounds(usize),
+    /// Reading a page from the underlying storage failed
+    Io(String),
+    /// Freelist page number out of the database or visited twice, or
+    /// trunk page listing too many leaves
+    BadFreelist(u32),
+    /// Number of pages in the freelist doesn't match the header
+    FreelistCountMismatch {
+        header: u32,
+        found: u32,
+    },
+    /// Pointer-map entry of an unknown type
+    BadPtrmapType(u8),
+    /// Pointer-map page decoded as a B-tree page
+    PtrmapPage(u32),
+    /// Page referenced twice by the B-trees, or referenced where it can't be
+    BadPageReference(u32),
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            page: None,
+            offset: None,
+        }
+    }
+
+    pub fn with_page(mut self, page: u32) -> Self {
+        self.page = Some(page);
+        self
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            Truncated => write!(f, "unexpected end of input"),
+            BadMagic => write!(f, "magic not found"),
+            UnsupportedFileFormat(v) => write!(f, "unsupported file format: {}", v),
+            UnsupportedTextEncoding(v) => write!(f, "unsupported text encoding: {}", v),
+            BadPageSize(v) => write!(f, "invalid page size: {}", v),
+            BadReservedBytes(v) => write!(f, "invalid reserved bytes per page: {}", v),
+            BadPayloadFractions => write!(f, "invalid payload fractions"),
+            BadPageType(v) => write!(f, "unsupported page type: {}", v),
+            BadCellPointer(v) => write!(f, "cell pointer ({}) beyond the end of the page", v),
+            BadFreeblock(v) => write!(f, "invalid freeblock at offset {}", v),
+            OverlappingContent(v) => write!(f, "content at offset {} overlaps", v),
+            BadVarint => write!(f, "invalid varint"),
+            BadSerialType(v) => write!(f, "unsupported serial type: {}", v),
+            BadRecordHeader => write!(f, "invalid record header"),
+            BadText => write!(f, "invalid text"),
+            BadOverflowChain => write!(f, "overflow chain ended before the end of the payload"),
+            MissingPage(v) => write!(f, "page ({}) not found in the database", v),
+            ColumnOutOfBounds(v) => write!(f, "column {} out of bounds", v),
+            Io(v) => write!(f, "failed to read page: {}", v),
+            BadFreelist(v) => write!(f, "invalid freelist entry: {}", v),
+            FreelistCountMismatch { header, found } => write!(
+                f,
+                "freelist has {} pages, the header expects {}",
+                found, header
+            ),
+            BadPtrmapType(v) => write!(f, "unsupported pointer-map entry type: {}", v),
+            PtrmapPage(v) => write!(f, "page ({}) is a pointer-map page", v),
+            BadPageReference(v) => write!(f, "invalid reference to page {}", v),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(page) = self.page {
+            write!(f, " on page {}", page)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::new(ErrorKind::Io(err.to_string()))
+    }
+}
+
+/// Internal nom error, remembering where in the input it occurred
+#[derive(Debug)]
+pub(crate) struct ParserError {
+    kind: ErrorKind,
+    /// Address of the input at the time of the error
+    position: Option<usize>,
+    page: Option<u32>,
+    offset: Option<usize>,
+}
+
+impl ParserError {
+    pub(crate) fn new(kind: ErrorKind, input: &[u8]) -> Self {
+        Self {
+            kind,
+            position: Some(input.as_ptr() as usize),
+            page: None,
+            offset: None,
+        }
+    }
+
+    pub(crate) fn without_position(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            position: None,
+            page: None,
+            offset: None,
+        }
+    }
+
+    pub(crate) fn failure(kind: ErrorKind, input: &[u8]) -> nom::Err<Self> {
+        nom::Err::Failure(Self::new(kind, input))
+    }
+
+    /// Resolve the position of the error relative to `base`, if the error
+    /// occurred in it and wasn't already resolved.
+    pub(crate) fn resolve(mut self, page: Option<u32>, base: &[u8]) -> Self {
+        if self.offset.is_none() && self.page.is_none() {
+            let start = base.as_ptr() as usize;
+            self.page = page;
+            self.offset = self
+                .position
+                .filter(|p| *p >= start && *p <= start + base.len())
+                .map(|p| p - start);
+        }
+        self
+    }
+
+    pub(crate) fn into_error(self) -> Error {
+        Error {
+            kind: self.kind,
+            page: self.page,
+            offset: self.offset,
+        }
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a [u8]> for ParserError {
+    fn from_error_kind(input: &'a [u8], _kind: nom::error::ErrorKind) -> Self {
+        // The only nom parser in use is `take`, which fails at the end of the
+        // input.
+        ParserError::new(ErrorKind::Truncated, input)
+    }
+    fn append(_input: &'a [u8], _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+    fn or(self, other: Self) -> Self {
+        other
+    }
+}
+
+/// Map a nom error to an [`Error`], resolving its offset relative to `base`.
+pub(crate) fn into_error(err: nom::Err<ParserError>, page: Option<u32>, base: &[u8]) -> Error {
+    match err {
+        nom::Err::Error(err) | nom::Err::Failure(err) => err.resolve(page, base).into_error(),
+        nom::Err::Incomplete(_) => {
+            let mut err = Error::new(ErrorKind::Truncated);
+            err.page = page;
+            err
+        }
+    }
+}
+
+/// Map a nom error that occurred on `page`, keeping the original error if
+/// it was already resolved.
+pub(crate) fn resolve_err(
+    err: nom::Err<ParserError>,
+    page: Option<u32>,
+    base: &[u8],
+) -> nom::Err<ParserError> {
+    err.map(|err| err.resolve(page, base))
+}
@@ -0,0 +1,183 @@
+//! https://www.sqlite.org/fileformat.html#the_freelist
+
+use crate::error::into_error;
+use crate::util::read_u32;
+use crate::IResult;
+use crate::{Error, ErrorKind, ParserError};
+use sqlite_types::Pager;
+use std::collections::HashSet;
+
+/// Unused pages of the database, as a chain of trunk pages each listing
+/// leaf pages.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Freelist {
+    pub trunks: Vec<FreelistTrunk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreelistTrunk {
+    pub page_number: u32,
+    pub leaves: Vec<u32>,
+}
+
+impl Freelist {
+    /// Number of free pages, trunk pages included
+    pub fn page_count(&self) -> u32 {
+        self.trunks
+            .iter()
+            .map(|trunk| 1 + trunk.leaves.len() as u32)
+            .sum()
+    }
+
+    /// Free page numbers, trunk pages included
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.trunks.iter().flat_map(|trunk| {
+            std::iter::once(trunk.page_number).chain(trunk.leaves.iter().copied())
+        })
+    }
+}
+
+/// Walk the freelist of the database, starting at the first trunk page in
+/// the header.
+/// Errors if the chain is malformed or if its number of pages doesn't match
+/// the header.
+pub fn decode<P: Pager + ?Sized>(pager: &P) -> Result<Freelist, Error> {
+    let header = pager.header();
+    let page_count = pager.page_count();
+    // Trunk pages list at most usable size / 4 - 2 leaves
+    let max_leaves = (header.usable_size() / 4).saturating_sub(2);
+
+    let mut freelist = Freelist::default();
+    let mut visited = HashSet::new();
+    let mut found = 0u32;
+    let mut next_trunk = header.page_num_first_freelist;
+
+    while next_trunk != 0 {
+        let page_number = next_trunk;
+        if page_number > page_count || !visited.insert(page_number) {
+            return Err(Error::new(ErrorKind::BadFreelist(page_number)));
+        }
+
+        let page = pager
+            .page(page_number)
+            .map_err(|err| Error::from(err).with_page(page_number))?
+            .ok_or_else(|| Error::new(ErrorKind::MissingPage(page_number)))?;
+
+        let (_, (next, leaves)) = decode_trunk(&page, max_leaves, page_count)
+            .map_err(|err| into_error(err, Some(page_number), &page))?;
+        for leaf in &leaves {
+            if !visited.insert(*leaf) {
+                return Err(Error::new(ErrorKind::BadFreelist(*leaf)));
+            }
+        }
+
+        found = found.saturating_add(1 + leaves.len() as u32);
+        freelist.trunks.push(FreelistTrunk {
+            page_number,
+            leaves,
+        });
+        next_trunk = next;
+    }
+
+    if found != header.page_count_freelist {
+        return Err(Error::new(ErrorKind::FreelistCountMismatch {
+            header: header.page_count_freelist,
+            found,
+        }));
+    }
+
+    Ok(freelist)
+}
+
+/// Returns the next trunk page and the leaf pages of the trunk
+fn decode_trunk(input: &[u8], max_leaves: u32, page_count: u32) -> IResult<&[u8], (u32, Vec<u32>)> {
+    let (input, next_trunk) = read_u32(input)?;
+
+    let start = input;
+    let (mut input, leaf_count) = read_u32(input)?;
+    if leaf_count > max_leaves {
+        return Err(ParserError::failure(
+            ErrorKind::BadFreelist(leaf_count),
+            start,
+        ));
+    }
+
+    let mut leaves = Vec::with_capacity(leaf_count as usize);
+    for _ in 0..leaf_count {
+        let start = input;
+        let (rest, leaf) = read_u32(input)?;
+        if leaf == 0 || leaf > page_count {
+            return Err(ParserError::failure(ErrorKind::BadFreelist(leaf), start));
+        }
+        leaves.push(leaf);
+        input = rest;
+    }
+
+    Ok((input, (next_trunk, leaves)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::create_db;
+
+    #[test]
+    fn it_walks_the_freelist() {
+        let db = create_db(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 1000)
+            insert into test select printf('%.*c', 200, 'x') from n;
+            delete from test;
+            ",
+        );
+        let freelist_count = db.header.page_count_freelist;
+
+        let freelist = decode(&db).unwrap();
+        assert!(freelist_count > 300);
+        assert_eq!(freelist.page_count(), freelist_count);
+        // A 512 bytes trunk page lists at most 126 leaves
+        assert!(freelist.trunks.len() > 1);
+
+        let pages: HashSet<u32> = freelist.pages().collect();
+        assert_eq!(pages.len(), freelist_count as usize);
+        assert!(!pages.contains(&1));
+        assert!(!pages.contains(&2));
+    }
+
+    #[test]
+    fn it_decodes_empty_freelist() {
+        let db = create_db("create table test (value text);");
+        assert_eq!(decode(&db).unwrap(), Freelist::default());
+    }
+
+    #[test]
+    fn it_validates_the_freelist() {
+        let mut db = create_db(
+            "
+            create table test (value text);
+            insert into test values (printf('%.*c', 10000, 'x'));
+            delete from test;
+            ",
+        );
+        let freelist_count = db.header.page_count_freelist;
+
+        db.header.page_count_freelist += 1;
+        let err = decode(&db).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::FreelistCountMismatch {
+                header: freelist_count + 1,
+                found: freelist_count
+            }
+        );
+        db.header.page_count_freelist -= 1;
+
+        // Point the first leaf of the trunk page back to the trunk itself
+        let trunk = db.header.page_num_first_freelist;
+        db.pages.get_mut(&trunk).unwrap()[8..12].copy_from_slice(&trunk.to_be_bytes());
+        let err = decode(&db).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadFreelist(trunk));
+    }
+}
@@ -0,0 +1,47 @@
+//! Entry points of the fuzz targets in `fuzz/fuzz_targets`, also replayed on
+//! a mutated corpus by the tests. They decode everything they can and ignore
+//! the errors, only panics are failures.
+
+use crate::{btree, db, freelist, pager, ptrmap, wal};
+use sqlite_types::Pager;
+
+pub fn exercise_db(data: &[u8]) {
+    let _ = db::decode_header(data);
+
+    if let Ok(db) = db::decode(data) {
+        exercise_pager(&db);
+    }
+    if let Ok(pager) = pager::BufferPager::new(data) {
+        exercise_pager(&pager);
+    }
+}
+
+pub fn exercise_pager<P: Pager>(pager: &P) {
+    let _ = freelist::decode(pager);
+    let _ = ptrmap::decode(pager);
+    let _ = ptrmap::compute(pager);
+
+    for page_number in 1..=pager.page_count() {
+        let btree = match btree::decode_page(pager, page_number) {
+            Ok(btree) => btree,
+            Err(_) => continue,
+        };
+        if let Ok(Some(page)) = pager.page(page_number) {
+            let _ = btree.layout(pager.header(), &page);
+        }
+
+        for cell in &btree.cells {
+            let payload = match cell {
+                btree::Cell::TableBTreeLeafCell(cell) => &cell.payload,
+                btree::Cell::IndexBTreeLeafCell(cell) => &cell.payload,
+                btree::Cell::IndexBTreeInteriorCell(cell) => &cell.payload,
+                btree::Cell::TableBTreeInteriorCell(_) => continue,
+            };
+            let _ = payload.records();
+        }
+    }
+}
+
+pub fn exercise_wal(data: &[u8]) {
+    let _ = wal::decode(data);
+}
@@ -0,0 +1,366 @@
+//! Byte ranges of the structures of a B-tree page
+//! See https://www.sqlite.org/fileformat.html#b_tree_pages
+
+use crate::btree::{self, Btree, PageContent, PageType};
+use crate::error::into_error;
+use crate::util::{read_u16, read_u32};
+use crate::{Error, ErrorKind, ParserError};
+use sqlite_types::DbHeader;
+use std::fmt;
+use std::ops::Range;
+
+/// Every byte of a page attributed to the structure it belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageLayout {
+    /// Contiguous regions sorted by offset, covering the whole page
+    pub regions: Vec<Region>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    /// Absolute offsets in the page
+    pub range: Range<usize>,
+    pub kind: RegionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    /// The 100 bytes database header on the first page
+    DbHeader,
+    BtreeHeader,
+    CellPointerArray,
+    /// Between the cell pointer array and the cell content area
+    Unallocated,
+    /// Left child page and varints preceding the payload of cell `n`
+    CellHeader(usize),
+    /// Part of the payload of cell `n` stored on the page
+    CellPayload(usize),
+    /// First overflow page number of cell `n`
+    CellOverflowPointer(usize),
+    Freeblock,
+    /// Free bytes in the cell content area not in a freeblock
+    Fragment,
+    /// Reserved bytes at the end of the page
+    Reserved,
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RegionKind::*;
+        match self {
+            DbHeader => write!(f, "database header"),
+            BtreeHeader => write!(f, "B-tree header"),
+            CellPointerArray => write!(f, "cell pointer array"),
+            Unallocated => write!(f, "unallocated space"),
+            CellHeader(n) => write!(f, "cell {} header", n),
+            CellPayload(n) => write!(f, "cell {} payload", n),
+            CellOverflowPointer(n) => write!(f, "cell {} overflow page pointer", n),
+            Freeblock => write!(f, "freeblock"),
+            Fragment => write!(f, "fragmented bytes"),
+            Reserved => write!(f, "reserved space"),
+        }
+    }
+}
+
+impl PageLayout {
+    /// Number of fragmented free bytes, should match the B-tree header
+    pub fn fragmented_bytes(&self) -> usize {
+        self.size_of(|kind| kind == RegionKind::Fragment)
+    }
+
+    /// Number of bytes in freeblocks
+    pub fn freeblock_bytes(&self) -> usize {
+        self.size_of(|kind| kind == RegionKind::Freeblock)
+    }
+
+    fn size_of(&self, f: impl Fn(RegionKind) -> bool) -> usize {
+        self.regions
+            .iter()
+            .filter(|region| f(region.kind))
+            .map(|region| region.range.len())
+            .sum()
+    }
+}
+
+impl<'a> Btree<'a> {
+    /// Locate the structures of the B-tree in `page`, the page it was decoded
+    /// from.
+    /// Errors if structures overlap or run past the usable size of the page.
+    pub fn layout(&self, db_header: &DbHeader, page: &[u8]) -> Result<PageLayout, Error> {
+        compute_layout(self, db_header, page).map_err(|err| into_error(err, None, page))
+    }
+}
+
+fn compute_layout(
+    btree: &Btree,
+    db_header: &DbHeader,
+    page: &[u8],
+) -> Result<PageLayout, nom::Err<ParserError>> {
+    let header = &btree.header;
+    let page_type = &header.page_type;
+    let usable_size = std::cmp::min(db_header.usable_size() as usize, page.len());
+
+    let mut regions = Vec::new();
+    if btree.header_offset > 0 {
+        regions.push(region(0..btree.header_offset, RegionKind::DbHeader));
+    }
+
+    let header_end = btree.header_offset + page_type.header_size() as usize;
+    regions.push(region(
+        btree.header_offset..header_end,
+        RegionKind::BtreeHeader,
+    ));
+    let pointers_end = header_end + 2 * header.cell_count as usize;
+    regions.push(region(
+        header_end..pointers_end,
+        RegionKind::CellPointerArray,
+    ));
+
+    let mut pointers = page.get(header_end..).unwrap_or_default();
+    for n in 0..header.cell_count as usize {
+        let (rest, pointer) = read_u16(pointers)?;
+        let start = pointer as usize;
+        let input = page
+            .get(start..usable_size)
+            .ok_or_else(|| ParserError::failure(ErrorKind::BadCellPointer(start), pointers))?;
+        pointers = rest;
+        let (header_size, payload_size) = cell_header(page_type, input)?;
+        let local_size = btree::local_payload_size(db_header, page_type, payload_size)
+            .map_err(|kind| ParserError::failure(kind, input))?;
+
+        let payload_start = start + header_size;
+        let payload_end = payload_start + local_size;
+        regions.push(region(start..payload_start, RegionKind::CellHeader(n)));
+        regions.push(region(
+            payload_start..payload_end,
+            RegionKind::CellPayload(n),
+        ));
+        if (local_size as u64) < payload_size {
+            regions.push(region(
+                payload_end..payload_end + 4,
+                RegionKind::CellOverflowPointer(n),
+            ));
+        }
+    }
+
+    // Freeblocks are chained in increasing order, which bounds the walk
+    let mut next = header.start_first_freeblock as usize;
+    let mut min = pointers_end;
+    while next != 0 {
+        let start = next;
+        let bad_freeblock = || {
+            ParserError::failure(
+                ErrorKind::BadFreeblock(start),
+                &page[start.min(page.len())..],
+            )
+        };
+        let input = page
+            .get(start..usable_size)
+            .filter(|_| start >= min)
+            .ok_or_else(bad_freeblock)?;
+        let (input, next_freeblock) = read_u16(input)?;
+        let (_, size) = read_u16(input)?;
+        if size < 4 {
+            return Err(bad_freeblock());
+        }
+
+        regions.push(region(start..start + size as usize, RegionKind::Freeblock));
+        next = next_freeblock as usize;
+        min = start + size as usize;
+    }
+
+    if usable_size < page.len() {
+        regions.push(region(usable_size..page.len(), RegionKind::Reserved));
+    }
+
+    // The content area starts at offset 65536 when the header says 0
+    let content_start = match header.start_cell_content_area {
+        0 => 65536,
+        v => v as usize,
+    };
+    fill_gaps(regions, page, content_start)
+}
+
+/// Returns the size of the cell header and the size of the payload
+fn cell_header(page_type: &PageType, input: &[u8]) -> Result<(usize, u64), nom::Err<ParserError>> {
+    let start = input.len();
+    let input = if page_type.is_interior() {
+        read_u32(input)?.0
+    } else {
+        input
+    };
+
+    let (input, payload_size) = match page_type {
+        PageType::Interior(PageContent::Table) => {
+            // Only the rowid, without payload
+            let (input, _) = btree::read_varint(input)?;
+            (input, 0)
+        }
+        PageType::Leaf(PageContent::Table) => {
+            let (input, (payload_size, _)) = btree::read_varint(input)?;
+            let (input, _) = btree::read_varint(input)?;
+            (input, payload_size)
+        }
+        PageType::Leaf(PageContent::Index) | PageType::Interior(PageContent::Index) => {
+            let (input, (payload_size, _)) = btree::read_varint(input)?;
+            (input, payload_size)
+        }
+    };
+
+    Ok((start - input.len(), payload_size))
+}
+
+/// Sort the regions and attribute the bytes between them to the unallocated
+/// space before the cell content area, and to fragments after it.
+fn fill_gaps(
+    mut regions: Vec<Region>,
+    page: &[u8],
+    content_start: usize,
+) -> Result<PageLayout, nom::Err<ParserError>> {
+    regions.sort_by_key(|region| region.range.start);
+
+    let mut layout = PageLayout {
+        regions: Vec::with_capacity(regions.len()),
+    };
+    let mut offset = 0;
+    for region in regions
+        .into_iter()
+        .filter(|region| !region.range.is_empty())
+    {
+        if region.range.start < offset || region.range.end > page.len() {
+            return Err(ParserError::failure(
+                ErrorKind::OverlappingContent(region.range.start),
+                page.get(region.range.start..).unwrap_or_default(),
+            ));
+        }
+
+        push_gap(&mut layout, offset..region.range.start, content_start);
+        offset = region.range.end;
+        layout.regions.push(region);
+    }
+    push_gap(&mut layout, offset..page.len(), content_start);
+
+    Ok(layout)
+}
+
+fn push_gap(layout: &mut PageLayout, gap: Range<usize>, content_start: usize) {
+    let split = gap.end.clamp(gap.start, content_start.max(gap.start));
+    for (range, kind) in [
+        (gap.start..split, RegionKind::Unallocated),
+        (split..gap.end, RegionKind::Fragment),
+    ] {
+        if !range.is_empty() {
+            layout.regions.push(region(range, kind));
+        }
+    }
+}
+
+fn region(range: Range<usize>, kind: RegionKind) -> Region {
+    Region { range, kind }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlite_types::{Db, Pager};
+    use test_support::create_db;
+
+    fn layout(db: &Db, page_number: u32) -> PageLayout {
+        let btree = btree::decode_page(db, page_number).unwrap();
+        let page = db.page(page_number).unwrap().unwrap();
+        btree.layout(&db.header, &page).unwrap()
+    }
+
+    fn assert_covers_page(layout: &PageLayout, page_size: usize) {
+        let mut offset = 0;
+        for region in &layout.regions {
+            assert_eq!(region.range.start, offset);
+            offset = region.range.end;
+        }
+        assert_eq!(offset, page_size);
+    }
+
+    #[test]
+    fn it_maps_every_byte_of_the_page() {
+        let db = create_db(
+            "
+            pragma page_size = 1024;
+            create table test (id integer primary key, value text);
+            insert into test values (1, 'a'), (2, printf('%.*c', 3000, 'b'));
+            ",
+        );
+
+        let layout = layout(&db, 1);
+        assert_covers_page(&layout, 1024);
+        assert_eq!(layout.regions[0], region(0..100, RegionKind::DbHeader));
+        assert_eq!(layout.regions[1], region(100..108, RegionKind::BtreeHeader));
+        assert_eq!(
+            layout.regions[2],
+            region(108..110, RegionKind::CellPointerArray)
+        );
+        assert_eq!(layout.regions[3].kind, RegionKind::Unallocated);
+
+        let layout = self::layout(&db, 2);
+        assert_covers_page(&layout, 1024);
+        let kinds: Vec<RegionKind> = layout.regions.iter().map(|r| r.kind).collect();
+        // Cells are allocated from the end of the page
+        assert_eq!(
+            kinds,
+            [
+                RegionKind::BtreeHeader,
+                RegionKind::CellPointerArray,
+                RegionKind::Unallocated,
+                RegionKind::CellHeader(1),
+                RegionKind::CellPayload(1),
+                RegionKind::CellOverflowPointer(1),
+                RegionKind::CellHeader(0),
+                RegionKind::CellPayload(0),
+            ]
+        );
+        assert_eq!(layout.regions[3].range.len(), 3);
+    }
+
+    #[test]
+    fn it_maps_freeblocks_and_fragments() {
+        let db = create_db(
+            "
+            pragma page_size = 1024;
+            create table test (id integer primary key, value text);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 10)
+            insert into test select i, printf('%.*c', 20, 'x') from n;
+            delete from test where id in (2, 5);
+            -- Reuses a freeblock, leaving 2 bytes too small for a freeblock
+            insert into test values (20, printf('%.*c', 18, 'x'));
+            ",
+        );
+
+        let btree = btree::decode_page(&db, 2).unwrap();
+        let layout = layout(&db, 2);
+        assert_covers_page(&layout, 1024);
+        assert!(layout.freeblock_bytes() > 0);
+        assert_eq!(
+            layout.fragmented_bytes(),
+            btree.header.fragmented_free_bytes_count as usize
+        );
+        assert!(layout.fragmented_bytes() > 0);
+    }
+
+    #[test]
+    fn it_rejects_overlapping_cells() {
+        let mut db = create_db(
+            "
+            create table test (value text);
+            insert into test values ('a'), ('b');
+            ",
+        );
+
+        let btree = btree::decode_page(&db, 2).unwrap().into_owned();
+        // Point the second cell pointer to the first cell plus one byte
+        let page = db.pages.get_mut(&2).unwrap();
+        let first = u16::from_be_bytes([page[8], page[9]]);
+        page[10..12].copy_from_slice(&(first + 1).to_be_bytes());
+
+        let err = btree.layout(&db.header, &db.pages[&2]).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::OverlappingContent(_)));
+    }
+}
@@ -1,24 +1,101 @@
 pub mod btree;
 pub mod db;
+mod error;
+pub mod freelist;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod layout;
+pub mod pager;
+pub mod ptrmap;
 mod util;
 pub mod wal;
 
-#[derive(Debug)]
-pub(crate) struct ParserError(String);
+pub(crate) use error::ParserError;
+pub use error::{Error, ErrorKind};
 
-impl<I> nom::error::ParseError<I> for ParserError {
-    fn from_error_kind(_input: I, kind: nom::error::ErrorKind) -> Self {
-        ParserError(format!("error {:?}", kind))
+pub(crate) type IResult<I, O, E = ParserError> = Result<(I, O), nom::Err<E>>;
+
+#[cfg(test)]
+mod tests {
+    use super::fuzz::{exercise_db, exercise_wal};
+    use std::fs;
+    use std::path::Path;
+
+    fn read_corpus(target: &str) -> Vec<Vec<u8>> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(target);
+        let mut corpus: Vec<Vec<u8>> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert!(!corpus.is_empty());
+
+        // Inputs too small to contain the structures
+        corpus.push(vec![]);
+        corpus.push(vec![0; 100]);
+        corpus
     }
-    fn append(_input: I, _kind: nom::error::ErrorKind, _other: Self) -> Self {
-        todo!()
+
+    /// xorshift64, to get reproducible mutations without dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n.max(1) as u64) as usize
+        }
     }
-    fn from_char(_input: I, _: char) -> Self {
-        todo!()
+
+    /// Replay the corpus with random corruptions: byte flips, overwritten
+    /// integers and truncations.
+    fn mutate_corpus(target: &str, iterations: usize, f: fn(&[u8])) {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+
+        for input in read_corpus(target) {
+            f(&input);
+
+            for _ in 0..iterations {
+                let mut data = input.clone();
+
+                for _ in 0..1 + rng.below(8) {
+                    if data.is_empty() {
+                        break;
+                    }
+                    let offset = rng.below(data.len());
+                    match rng.below(3) {
+                        0 => data[offset] ^= 1 << rng.below(8),
+                        1 => data[offset] = rng.next() as u8,
+                        _ => {
+                            let end = (offset + 4).min(data.len());
+                            for b in &mut data[offset..end] {
+                                *b = 0xff;
+                            }
+                        }
+                    }
+                }
+                if rng.below(8) == 0 {
+                    data.truncate(rng.below(data.len()));
+                }
+
+                f(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn it_decodes_corrupted_databases_without_panicking() {
+        mutate_corpus("decode_db", 500, exercise_db);
     }
-    fn or(self, _other: Self) -> Self {
-        todo!()
+
+    #[test]
+    fn it_decodes_corrupted_wals_without_panicking() {
+        mutate_corpus("decode_wal", 500, exercise_wal);
     }
 }
-
-pub(crate) type IResult<I, O, E = ParserError> = Result<(I, O), nom::Err<E>>;
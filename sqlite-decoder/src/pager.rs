@@ -0,0 +1,220 @@
+//! Pagers reading the pages of a database on demand, instead of decoding
+//! the whole file into a `Db`.
+use crate::db::decode_header;
+use crate::{Error, ErrorKind};
+use memmap2::Mmap;
+use sqlite_types::{DbHeader, DbSizeSource, Pager};
+use std::borrow::Cow;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+/// Pager over a database held in memory, pages borrow from the buffer.
+pub struct BufferPager<B> {
+    header: DbHeader,
+    db_size_source: DbSizeSource,
+    buffer: B,
+}
+
+/// Pager over a memory-mapped database file
+pub type MmapPager = BufferPager<Mmap>;
+
+impl<B: AsRef<[u8]>> BufferPager<B> {
+    pub fn new(buffer: B) -> Result<Self, Error> {
+        let mut header = decode_header(buffer.as_ref())?;
+        let file_page_count = buffer.as_ref().len() as u64 / header.page_size as u64;
+        let db_size_source = resolve_db_size(&mut header, file_page_count);
+
+        Ok(Self {
+            header,
+            db_size_source,
+            buffer,
+        })
+    }
+
+    /// Where the number of pages was taken from
+    pub fn db_size_source(&self) -> DbSizeSource {
+        self.db_size_source
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+}
+
+impl MmapPager {
+    /// Map `file` in memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it's mapped, see
+    /// `memmap2::Mmap::map`.
+    pub unsafe fn map(file: &File) -> Result<Self, Error> {
+        Self::new(Mmap::map(file)?)
+    }
+}
+
+impl<B: AsRef<[u8]>> Pager for BufferPager<B> {
+    fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    fn page(&self, page_number: u32) -> io::Result<Option<Cow<'_, [u8]>>> {
+        if page_number == 0 || page_number > self.header.db_size {
+            return Ok(None);
+        }
+
+        let page_size = self.header.page_size as usize;
+        let start = (page_number as usize - 1) * page_size;
+        Ok(self
+            .buffer
+            .as_ref()
+            .get(start..start + page_size)
+            .map(Cow::Borrowed))
+    }
+
+    fn page_count(&self) -> u32 {
+        self.header.db_size
+    }
+}
+
+/// Pager reading each page from a file, or any `Read + Seek`, when it's
+/// requested.
+pub struct FilePager<R> {
+    header: DbHeader,
+    db_size_source: DbSizeSource,
+    reader: Mutex<R>,
+}
+
+impl<R: Read + Seek> FilePager<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header_bytes = [0u8; 100];
+        reader.seek(SeekFrom::Start(0))?;
+        reader
+            .read_exact(&mut header_bytes)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => Error::new(ErrorKind::Truncated),
+                _ => Error::from(err),
+            })?;
+        let mut header = decode_header(&header_bytes)?;
+
+        let file_page_count = reader.seek(SeekFrom::End(0))? / header.page_size as u64;
+        let db_size_source = resolve_db_size(&mut header, file_page_count);
+
+        Ok(Self {
+            header,
+            db_size_source,
+            reader: Mutex::new(reader),
+        })
+    }
+
+    /// Where the number of pages was taken from
+    pub fn db_size_source(&self) -> DbSizeSource {
+        self.db_size_source
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<R: Read + Seek> Pager for FilePager<R> {
+    fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    fn page(&self, page_number: u32) -> io::Result<Option<Cow<'_, [u8]>>> {
+        if page_number == 0 || page_number > self.header.db_size {
+            return Ok(None);
+        }
+
+        let page_size = self.header.page_size as u64;
+        let mut page = vec![0u8; page_size as usize];
+
+        // A panic while holding the lock doesn't leave the reader in an
+        // invalid state, the next read seeks anyway.
+        let mut reader = self.reader.lock().unwrap_or_else(|err| err.into_inner());
+        reader.seek(SeekFrom::Start((page_number as u64 - 1) * page_size))?;
+        reader.read_exact(&mut page)?;
+
+        Ok(Some(Cow::Owned(page)))
+    }
+
+    fn page_count(&self) -> u32 {
+        self.header.db_size
+    }
+}
+
+/// Apply the in-header database size rule to a file of `file_page_count`
+/// pages, see `DbHeader::resolve_db_size`
+fn resolve_db_size(header: &mut DbHeader, file_page_count: u64) -> DbSizeSource {
+    let file_page_count = cmp::min(file_page_count, u32::MAX as u64) as u32;
+    let (db_size, db_size_source) = header.resolve_db_size(file_page_count);
+    header.db_size = db_size;
+    db_size_source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree;
+    use test_support::create_db_file;
+
+    fn table_values<P: Pager>(pager: &P, page_number: u32) -> Vec<String> {
+        let btree = btree::decode_page(pager, page_number).unwrap();
+        btree
+            .cells
+            .iter()
+            .map(|cell| match cell {
+                btree::Cell::TableBTreeLeafCell(cell) => {
+                    cell.payload.get(0).unwrap().as_str().unwrap().to_owned()
+                }
+                cell => panic!("unexpected cell: {:?}", cell),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_reads_pages_on_demand() {
+        let file = create_db_file(
+            "
+            create table test (value text);
+            insert into test values ('small');
+            insert into test values (printf('%.*c', 10000, 'a'));
+            ",
+        );
+        let expected = vec!["small".to_owned(), "a".repeat(10000)];
+
+        let bytes = std::fs::read(file.path()).unwrap();
+        let db = crate::db::decode(&bytes).unwrap();
+        assert_eq!(table_values(&db, 2), expected);
+
+        let pager = BufferPager::new(&bytes).unwrap();
+        assert_eq!(pager.page_count(), db.header.db_size);
+        assert_eq!(table_values(&pager, 2), expected);
+
+        let pager = FilePager::new(File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(pager.page_count(), db.header.db_size);
+        assert_eq!(table_values(&pager, 2), expected);
+        assert_eq!(pager.page(2).unwrap().unwrap(), db.pages[&2]);
+        assert!(pager.page(0).unwrap().is_none());
+        assert!(pager.page(db.header.db_size + 1).unwrap().is_none());
+
+        let pager = unsafe { MmapPager::map(&File::open(file.path()).unwrap()) }.unwrap();
+        assert_eq!(table_values(&pager, 2), expected);
+
+        let err = btree::decode_page(&pager, 42).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingPage(42));
+    }
+
+    #[test]
+    fn it_rejects_truncated_file() {
+        let err = FilePager::new(io::Cursor::new(vec![0u8; 10]))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::Truncated);
+    }
+}
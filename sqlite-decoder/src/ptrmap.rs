@@ -0,0 +1,277 @@
+//! https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages
+
+use crate::btree::{self, Cell, PageType, RecordView};
+use crate::{freelist, Error, ErrorKind};
+use sqlite_types::{Pager, PtrmapEntry, PtrmapType};
+use std::collections::{HashMap, HashSet};
+
+/// Pointer-map entries, keyed by the page they describe
+pub type Ptrmap = HashMap<u32, PtrmapEntry>;
+
+/// Decode the pointer-map pages of the database
+/// Unused entries (type 0) are skipped. Returns an empty map if the
+/// database isn't in auto-vacuum mode.
+pub fn decode<P: Pager + ?Sized>(pager: &P) -> Result<Ptrmap, Error> {
+    let header = pager.header();
+    let mut ptrmap = HashMap::new();
+    let mut current: Option<(u32, Vec<u8>)> = None;
+
+    for page_number in 3..=pager.page_count() {
+        let (ptrmap_page, offset) = match header.ptrmap_location(page_number) {
+            Some(location) => location,
+            None => continue,
+        };
+
+        if current.as_ref().map(|(n, _)| *n) != Some(ptrmap_page) {
+            let page = pager
+                .page(ptrmap_page)
+                .map_err(|err| Error::from(err).with_page(ptrmap_page))?
+                .ok_or_else(|| Error::new(ErrorKind::MissingPage(ptrmap_page)))?;
+            current = Some((ptrmap_page, page.into_owned()));
+        }
+        let page = &current.as_ref().unwrap().1;
+
+        let entry = page.get(offset..offset + 5).ok_or_else(|| {
+            let mut err = Error::new(ErrorKind::Truncated).with_page(ptrmap_page);
+            err.offset = Some(offset);
+            err
+        })?;
+        let page_type = match entry[0] {
+            0 => continue,
+            1 => PtrmapType::RootPage,
+            2 => PtrmapType::FreePage,
+            3 => PtrmapType::Overflow1,
+            4 => PtrmapType::Overflow2,
+            5 => PtrmapType::Btree,
+            e => {
+                let mut err = Error::new(ErrorKind::BadPtrmapType(e)).with_page(ptrmap_page);
+                err.offset = Some(offset);
+                return Err(err);
+            }
+        };
+        let parent = u32::from_be_bytes(entry[1..5].try_into().unwrap());
+
+        ptrmap.insert(page_number, PtrmapEntry { page_type, parent });
+    }
+
+    Ok(ptrmap)
+}
+
+/// Compute the pointer-map entries the database should have, by walking
+/// every B-tree listed in the schema, their overflow chains and the
+/// freelist.
+/// Useful to check or rebuild the pointer-map pages.
+pub fn compute<P: Pager + ?Sized>(pager: &P) -> Result<Ptrmap, Error> {
+    let mut walker = Walker {
+        pager,
+        ptrmap: HashMap::new(),
+        visited: HashSet::new(),
+    };
+
+    // The schema table is rooted on page 1, which has no entry
+    let mut root_pages = Vec::new();
+    walker.walk(1, None, &mut |cell| {
+        if let Cell::TableBTreeLeafCell(cell) = cell {
+            if let Some(root_page) = cell.payload.get(3)?.as_int() {
+                root_pages.push(root_page as u32);
+            }
+        }
+        Ok(())
+    })?;
+
+    for root_page in root_pages {
+        // Views and triggers don't have a B-tree
+        if root_page == 0 {
+            continue;
+        }
+        walker.insert(root_page, PtrmapType::RootPage, 0)?;
+        walker.walk(root_page, None, &mut |_| Ok(()))?;
+    }
+
+    for page_number in freelist::decode(pager)?.pages() {
+        walker.insert(page_number, PtrmapType::FreePage, 0)?;
+    }
+
+    Ok(walker.ptrmap)
+}
+
+struct Walker<'a, P: ?Sized> {
+    pager: &'a P,
+    ptrmap: Ptrmap,
+    /// B-tree pages already walked, to avoid looping on corrupted trees
+    visited: HashSet<u32>,
+}
+
+impl<'a, P: Pager + ?Sized> Walker<'a, P> {
+    fn insert(
+        &mut self,
+        page_number: u32,
+        page_type: PtrmapType,
+        parent: u32,
+    ) -> Result<(), Error> {
+        if self.pager.header().ptrmap_location(page_number).is_none() {
+            return Err(Error::new(ErrorKind::BadPageReference(page_number)));
+        }
+        self.ptrmap
+            .insert(page_number, PtrmapEntry { page_type, parent });
+        Ok(())
+    }
+
+    /// Walk the B-tree rooted at `page_number`, recording the entries of its
+    /// child and overflow pages.
+    fn walk(
+        &mut self,
+        page_number: u32,
+        parent: Option<u32>,
+        on_cell: &mut dyn FnMut(&Cell) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if !self.visited.insert(page_number) {
+            return Err(Error::new(ErrorKind::BadPageReference(page_number)));
+        }
+        if let Some(parent) = parent {
+            self.insert(page_number, PtrmapType::Btree, parent)?;
+        }
+
+        let btree = btree::decode_page(self.pager, page_number)?;
+        for cell in &btree.cells {
+            on_cell(cell)?;
+
+            let (payload, page_first_overflow) = match cell {
+                Cell::TableBTreeInteriorCell(cell) => {
+                    self.walk(cell.left_child_page, Some(page_number), on_cell)?;
+                    continue;
+                }
+                Cell::IndexBTreeInteriorCell(cell) => {
+                    self.walk(cell.left_child_page, Some(page_number), on_cell)?;
+                    (&cell.payload, cell.page_first_overflow)
+                }
+                Cell::TableBTreeLeafCell(cell) => (&cell.payload, cell.page_first_overflow),
+                Cell::IndexBTreeLeafCell(cell) => (&cell.payload, cell.page_first_overflow),
+            };
+
+            if let Some(page_first_overflow) = page_first_overflow {
+                self.walk_overflow(
+                    page_number,
+                    &btree.header.page_type,
+                    payload,
+                    page_first_overflow,
+                )?;
+            }
+        }
+
+        if let Some(right_most_pointer) = btree.header.right_most_pointer {
+            self.walk(right_most_pointer, Some(page_number), on_cell)?;
+        }
+
+        Ok(())
+    }
+
+    fn walk_overflow(
+        &mut self,
+        page_number: u32,
+        page_type: &PageType,
+        payload: &RecordView,
+        page_first_overflow: u32,
+    ) -> Result<(), Error> {
+        let header = self.pager.header();
+        let payload_size = payload.payload().len();
+        let local_size = btree::local_payload_size(header, page_type, payload_size as u64)
+            .map_err(|kind| Error::new(kind).with_page(page_number))?;
+        let overflow_size = header.usable_size() as usize - 4;
+        let overflow_count = (payload_size - local_size).div_ceil(overflow_size);
+
+        // The chain was already followed when decoding the payload
+        let mut parent = page_number;
+        let mut current = page_first_overflow;
+        for i in 0..overflow_count {
+            let page_type = if i == 0 {
+                PtrmapType::Overflow1
+            } else {
+                PtrmapType::Overflow2
+            };
+            self.insert(current, page_type, parent)?;
+
+            let page = self
+                .pager
+                .page(current)
+                .map_err(|err| Error::from(err).with_page(current))?
+                .ok_or_else(|| Error::new(ErrorKind::MissingPage(current)))?;
+            let next = page
+                .get(0..4)
+                .ok_or_else(|| Error::new(ErrorKind::Truncated).with_page(current))?;
+            parent = current;
+            current = u32::from_be_bytes(next.try_into().unwrap());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::create_db;
+
+    #[test]
+    fn it_decodes_ptrmap_pages() {
+        let db = create_db(
+            "
+            pragma page_size = 512;
+            pragma auto_vacuum = incremental;
+            create table test (id integer primary key, value text);
+            create index test_value on test (value);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 300)
+            insert into test select i, printf('%.*c', i * 3, 'x') from n;
+            insert into test values (1000, printf('%.*c', 5000, 'y'));
+            delete from test where id % 7 = 0;
+            ",
+        );
+        assert!(db.header.has_ptrmap());
+        // Enough pages for more than one pointer-map page
+        assert!(db.header.db_size > 2 + 512 / 5 + 1);
+        assert!(db.header.is_ptrmap_page(2));
+        assert!(db.header.is_ptrmap_page(2 + 512 / 5 + 1));
+
+        let ptrmap = decode(&db).unwrap();
+        assert_eq!(
+            ptrmap[&3],
+            PtrmapEntry {
+                page_type: PtrmapType::RootPage,
+                parent: 0
+            }
+        );
+        for page_type in [
+            PtrmapType::FreePage,
+            PtrmapType::Overflow1,
+            PtrmapType::Overflow2,
+            PtrmapType::Btree,
+        ] {
+            assert!(ptrmap.values().any(|entry| entry.page_type == page_type));
+        }
+
+        // Every page but page 1 and the pointer-map pages has an entry
+        let expected = (1..=db.header.db_size)
+            .filter(|n| *n != 1 && !db.header.is_ptrmap_page(*n))
+            .count();
+        assert_eq!(ptrmap.len(), expected);
+        assert_eq!(compute(&db).unwrap(), ptrmap);
+    }
+
+    #[test]
+    fn it_recognizes_ptrmap_pages() {
+        let db = create_db(
+            "
+            pragma auto_vacuum = full;
+            create table test (value text);
+            ",
+        );
+
+        let err = btree::decode_page(&db, 2).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PtrmapPage(2));
+        assert!(btree::decode_page(&db, 3).is_ok());
+
+        let db = create_db("create table test (value text);");
+        assert!(!db.header.has_ptrmap());
+        assert!(decode(&db).unwrap().is_empty());
+    }
+}
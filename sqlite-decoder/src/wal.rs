@@ -1,38 +1,77 @@
 //! https://sqlite.org/fileformat.html#walformat
 
+use crate::error::into_error;
 use crate::IResult;
-use crate::ParserError;
+use crate::{Error, ErrorKind, ParserError};
 use nom::bytes::complete::take;
 use sqlite_types::{
-    Wal, WalFrame, WalFrameHeader, WalHeader, MAGIC_NUMBER_1, MAGIC_NUMBER_2, SUPPORTED_FILE_FORMAT,
+    Wal, WalFrame, WalFrameHeader, WalHeader, WalStopReason, MAGIC_NUMBER_1, MAGIC_NUMBER_2,
+    SUPPORTED_FILE_FORMAT,
 };
 
-type BoxError = Box<dyn std::error::Error>;
+const FRAME_HEADER_SIZE: usize = 24;
 
-pub fn decode(input: &[u8]) -> Result<Wal, BoxError> {
+/// Decode a WAL file
+/// Like SQLite when recovering the WAL, decoding stops at the first frame
+/// whose salts, page number or checksum are invalid, see `Wal::stop_reason`.
+/// Error offsets are relative to the start of the file.
+pub fn decode(input: &[u8]) -> Result<Wal, Error> {
     match decode_wal(input) {
         Ok((_, wal)) => Ok(wal),
-        Err(err) => Err(format!("failed to decode: {}", err).into()),
+        Err(err) => Err(into_error(err, None, input)),
     }
 }
 
 fn decode_wal(input: &[u8]) -> IResult<&[u8], Wal> {
     let (input, input_header) = take(32usize)(input)?;
-    let (_, header) = decode_header(&input_header)?;
+    let (_, header) = decode_header(input_header)?;
 
     let mut frames = vec![];
     let mut input = input;
-    loop {
-        if input.len() < header.page_size as usize {
-            // EOF or not enough bytes to continue
-            break;
+
+    // Each frame checksum covers the header and all the frames before it
+    let (mut checksum_1, mut checksum_2) = header.checksum();
+    let stop_reason = if (checksum_1, checksum_2) != (header.checksum_1, header.checksum_2) {
+        WalStopReason::BadHeaderChecksum
+    } else {
+        loop {
+            if input.len() < FRAME_HEADER_SIZE + header.page_size as usize {
+                // EOF or not enough bytes to continue
+                break WalStopReason::EndOfFile;
+            }
+
+            let (rest, frame) = decode_frame(input, &header)?;
+            if header.salt_1 != frame.header.salt_1 || header.salt_2 != frame.header.salt_2 {
+                break WalStopReason::SaltMismatch {
+                    frame: frames.len(),
+                };
+            }
+            if frame.header.page_number == 0 {
+                break WalStopReason::BadPageNumber {
+                    frame: frames.len(),
+                };
+            }
+
+            (checksum_1, checksum_2) = header.frame_checksum(&frame, checksum_1, checksum_2);
+            if (checksum_1, checksum_2) != (frame.header.checksum_1, frame.header.checksum_2) {
+                break WalStopReason::BadChecksum {
+                    frame: frames.len(),
+                };
+            }
+
+            input = rest;
+            frames.push(frame);
         }
+    };
 
-        let ret = decode_frame(&input, &header)?;
-        input = ret.0;
-        frames.push(ret.1);
-    }
-    Ok((input, Wal { header, frames }))
+    Ok((
+        input,
+        Wal {
+            header,
+            frames,
+            stop_reason,
+        },
+    ))
 }
 
 fn read_u32(input: &[u8]) -> IResult<&[u8], u32> {
@@ -41,24 +80,31 @@ fn read_u32(input: &[u8]) -> IResult<&[u8], u32> {
 }
 
 fn decode_header(input: &[u8]) -> IResult<&[u8], WalHeader> {
+    let start = input;
     let (input, magic_number) = read_u32(input)?;
 
     if magic_number != MAGIC_NUMBER_1 && magic_number != MAGIC_NUMBER_2 {
-        return Err(nom::Err::Failure(ParserError(format!(
-            "magic number not found, got: {:?}",
-            magic_number
-        ))));
+        return Err(ParserError::failure(ErrorKind::BadMagic, start));
     }
 
+    let start = input;
     let (input, file_format) = read_u32(input)?;
 
     if file_format != SUPPORTED_FILE_FORMAT {
-        return Err(nom::Err::Failure(ParserError(format!(
-            "unsupported file format"
-        ))));
+        return Err(ParserError::failure(
+            ErrorKind::UnsupportedFileFormat(file_format),
+            start,
+        ));
     }
 
+    let page_size_input = input;
     let (input, page_size) = read_u32(input)?;
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+        return Err(ParserError::failure(
+            ErrorKind::BadPageSize(page_size),
+            page_size_input,
+        ));
+    }
     let (input, checkpoint_seq) = read_u32(input)?;
     let (input, salt_1) = read_u32(input)?;
     let (input, salt_2) = read_u32(input)?;
@@ -101,15 +147,9 @@ fn decode_frame_header(input: &[u8]) -> IResult<&[u8], WalFrameHeader> {
     ))
 }
 
-fn decode_frame<'a, 'b>(input: &'a [u8], wal_header: &'b WalHeader) -> IResult<&'a [u8], WalFrame> {
-    let (input, input_frame_header) = take(24usize)(input)?;
-    let (_, frame_header) = decode_frame_header(&input_frame_header)?;
-
-    if wal_header.salt_1 != frame_header.salt_1 || wal_header.salt_2 != frame_header.salt_2 {
-        return Err(nom::Err::Failure(ParserError(format!("Salt don't match"))));
-    }
-
-    // FIXME: check for `The checksum values in the final 8 bytes of the frame-header exactly match the checksum computed consecutively on the first 24 bytes of the WAL header and the first 8 bytes and the content of all frames up to and including the current frame.`
+fn decode_frame<'a>(input: &'a [u8], wal_header: &WalHeader) -> IResult<&'a [u8], WalFrame> {
+    let (input, input_frame_header) = take(FRAME_HEADER_SIZE)(input)?;
+    let (_, frame_header) = decode_frame_header(input_frame_header)?;
 
     let (input, data) = take(wal_header.page_size)(input)?;
 
@@ -121,3 +161,89 @@ fn decode_frame<'a, 'b>(input: &'a [u8], wal_header: &'b WalHeader) -> IResult<&
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::create_db_and_wal;
+
+    /// WAL of a database with 512 bytes pages after each batch of `sql`
+    fn create_wal(sql: &[&str]) -> Vec<u8> {
+        create_db_and_wal("pragma page_size = 512;", sql).1
+    }
+
+    fn frame_count(bytes: &[u8]) -> usize {
+        (bytes.len() - 32) / (FRAME_HEADER_SIZE + 512)
+    }
+
+    #[test]
+    fn it_decodes_all_valid_frames() {
+        let bytes = create_wal(&["
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            "]);
+        let wal = decode(&bytes).unwrap();
+        assert_eq!(wal.stop_reason, WalStopReason::EndOfFile);
+        assert_eq!(wal.frames.len(), frame_count(&bytes));
+    }
+
+    #[test]
+    fn it_stops_at_frames_from_a_previous_generation() {
+        // After a checkpoint, the next writer restarts the WAL with new
+        // salts and leaves the old frames behind the new ones
+        let bytes = create_wal(&[
+            "
+            create table test (value text);
+            insert into test values (printf('%.*c', 5000, 'x'));
+            ",
+            "pragma wal_checkpoint(restart);",
+            "insert into test values ('a');",
+        ]);
+        let wal = decode(&bytes).unwrap();
+        assert!(wal.frames.len() < frame_count(&bytes));
+        assert_eq!(
+            wal.stop_reason,
+            WalStopReason::SaltMismatch {
+                frame: wal.frames.len()
+            }
+        );
+    }
+
+    #[test]
+    fn it_stops_at_bad_checksums() {
+        let bytes = create_wal(&["
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            "]);
+        assert!(frame_count(&bytes) > 2);
+
+        // Corrupt the content of the second frame
+        let mut corrupted = bytes.clone();
+        corrupted[32 + (FRAME_HEADER_SIZE + 512) + FRAME_HEADER_SIZE + 10] ^= 0xff;
+        let wal = decode(&corrupted).unwrap();
+        assert_eq!(wal.frames.len(), 1);
+        assert_eq!(wal.stop_reason, WalStopReason::BadChecksum { frame: 1 });
+
+        let mut corrupted = bytes;
+        corrupted[28] ^= 0xff;
+        let wal = decode(&corrupted).unwrap();
+        assert!(wal.frames.is_empty());
+        assert_eq!(wal.stop_reason, WalStopReason::BadHeaderChecksum);
+    }
+
+    #[test]
+    fn it_stops_at_frames_of_page_0() {
+        let mut bytes = create_wal(&["
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            "]);
+        assert!(frame_count(&bytes) > 2);
+
+        // Page numbers are checked before the checksum, like SQLite does
+        let frame_start = 32 + (FRAME_HEADER_SIZE + 512);
+        bytes[frame_start..frame_start + 4].copy_from_slice(&0u32.to_be_bytes());
+        let wal = decode(&bytes).unwrap();
+        assert_eq!(wal.frames.len(), 1);
+        assert_eq!(wal.stop_reason, WalStopReason::BadPageNumber { frame: 1 });
+    }
+}
@@ -0,0 +1,395 @@
+//! https://www.sqlite.org/fileformat.html#b_tree_pages
+use crate::record::{varint_size, write_varint};
+use crate::BoxError;
+use sqlite_types::{DbHeader, Page};
+
+const TABLE_INTERIOR_PAGE: u8 = 0x05;
+const INDEX_LEAF_PAGE: u8 = 0x0a;
+const TABLE_LEAF_PAGE: u8 = 0x0d;
+
+/// Cell of a table leaf page
+#[derive(Debug, Clone)]
+pub struct TableLeafCell<'a> {
+    pub rowid: u64,
+    /// Size of the complete record
+    pub payload_size: u64,
+    /// Part of the record stored on the page, see `DbHeader::local_payload_size`
+    pub local_payload: &'a [u8],
+    /// First overflow page holding the rest of the record
+    pub first_overflow_page: Option<u32>,
+}
+
+/// Cell of a table interior page
+#[derive(Debug, Clone)]
+pub struct TableInteriorCell {
+    pub left_child_page: u32,
+    /// Largest rowid of the left child
+    pub rowid: u64,
+}
+
+impl<'a> TableLeafCell<'a> {
+    /// Number of bytes the cell takes in the cell content area
+    pub fn size(&self) -> usize {
+        let size = varint_size(self.payload_size)
+            + varint_size(self.rowid)
+            + self.local_payload.len()
+            + self.first_overflow_page.map_or(0, |_| 4);
+        // Cells can be turned into freeblocks, which take at least 4 bytes
+        size.max(4)
+    }
+
+    fn write(&self, writer: &mut Vec<u8>) {
+        write_varint(writer, self.payload_size);
+        write_varint(writer, self.rowid);
+        writer.extend_from_slice(self.local_payload);
+        if let Some(page) = self.first_overflow_page {
+            writer.extend(page.to_be_bytes());
+        }
+    }
+}
+
+impl TableInteriorCell {
+    /// Number of bytes the cell takes in the cell content area
+    pub fn size(&self) -> usize {
+        4 + varint_size(self.rowid)
+    }
+
+    fn write(&self, writer: &mut Vec<u8>) {
+        writer.extend(self.left_child_page.to_be_bytes());
+        write_varint(writer, self.rowid);
+    }
+}
+
+/// Space available on a page for the cells and their pointers
+pub fn cell_space(header: &DbHeader, page_number: u32, interior: bool) -> usize {
+    let header_size = if interior { 12 } else { 8 };
+    header.usable_size() as usize - page_header_offset(page_number) - header_size
+}
+
+/// Encode a table leaf page (0x0d) holding `cells`, in rowid order
+/// Errors if the cells don't fit on the page, or if a cell doesn't split its
+/// payload between the page and the overflow pages like SQLite does.
+pub fn encode_table_leaf(
+    header: &DbHeader,
+    page_number: u32,
+    cells: &[TableLeafCell],
+) -> Result<Page, BoxError> {
+    for cell in cells {
+        check_leaf_cell(header, cell)?;
+    }
+
+    let cells = cells.iter().map(|cell| {
+        let mut buff = Vec::with_capacity(cell.size());
+        cell.write(&mut buff);
+        buff.resize(cell.size(), 0);
+        buff
+    });
+    encode_page(header, page_number, TABLE_LEAF_PAGE, None, cells)
+}
+
+fn check_leaf_cell(header: &DbHeader, cell: &TableLeafCell) -> Result<(), BoxError> {
+    let local_size = header
+        .local_payload_size(true, cell.payload_size)
+        .ok_or("invalid payload fractions")?;
+    if cell.local_payload.len() != local_size {
+        return Err(format!(
+            "cell of rowid {} has {} bytes of local payload, expected {}",
+            cell.rowid,
+            cell.local_payload.len(),
+            local_size
+        )
+        .into());
+    }
+
+    let spills = (local_size as u64) < cell.payload_size;
+    match (spills, cell.first_overflow_page) {
+        (true, None) => Err(format!(
+            "cell of rowid {} spills without an overflow page",
+            cell.rowid
+        )
+        .into()),
+        (false, Some(_)) => Err(format!(
+            "cell of rowid {} fits on the page but has an overflow page",
+            cell.rowid
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Encode a table interior page (0x05) holding `cells`, in rowid order,
+/// with the rows after the last cell in `right_most_pointer`
+/// Errors if the cells don't fit on the page.
+pub fn encode_table_interior(
+    header: &DbHeader,
+    page_number: u32,
+    cells: &[TableInteriorCell],
+    right_most_pointer: u32,
+) -> Result<Page, BoxError> {
+    let cells = cells.iter().map(|cell| {
+        let mut buff = Vec::with_capacity(cell.size());
+        cell.write(&mut buff);
+        buff
+    });
+    encode_page(
+        header,
+        page_number,
+        TABLE_INTERIOR_PAGE,
+        Some(right_most_pointer),
+        cells,
+    )
+}
+
+/// Encode an index leaf page (0x0a) without cells, the root page of a new
+/// index
+pub fn encode_empty_index_leaf(header: &DbHeader, page_number: u32) -> Result<Page, BoxError> {
+    encode_page(
+        header,
+        page_number,
+        INDEX_LEAF_PAGE,
+        None,
+        std::iter::empty::<Vec<u8>>(),
+    )
+}
+
+/// Whether `page` is a table leaf page without cells, as the root page of a
+/// new table
+pub fn is_empty_table_leaf(page: &[u8], page_number: u32) -> bool {
+    let offset = page_header_offset(page_number);
+    page.get(offset) == Some(&TABLE_LEAF_PAGE)
+        && page.get(offset + 3..offset + 5) == Some(&[0, 0][..])
+}
+
+/// The first page starts with the database header, left empty here
+fn page_header_offset(page_number: u32) -> usize {
+    if page_number == 1 {
+        100
+    } else {
+        0
+    }
+}
+
+fn encode_page(
+    header: &DbHeader,
+    page_number: u32,
+    page_type: u8,
+    right_most_pointer: Option<u32>,
+    cells: impl ExactSizeIterator<Item = Vec<u8>>,
+) -> Result<Page, BoxError> {
+    let mut page = vec![0u8; header.page_size as usize];
+    let offset = page_header_offset(page_number);
+    let header_size = if right_most_pointer.is_some() { 12 } else { 8 };
+    let cell_count = cells.len();
+    if cell_count > u16::MAX as usize {
+        return Err(format!("too many cells for page {}: {}", page_number, cell_count).into());
+    }
+
+    // The cell content area grows from the end of the usable space, while
+    // the cell pointer array grows after the header
+    let mut pointer = offset + header_size;
+    let pointers_end = pointer + 2 * cell_count;
+    let mut content_start = header.usable_size() as usize;
+    for cell in cells {
+        if content_start < pointers_end + cell.len() {
+            return Err(format!("cells don't fit on page {}", page_number).into());
+        }
+        content_start -= cell.len();
+
+        page[content_start..content_start + cell.len()].copy_from_slice(&cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+
+    let btree_header = &mut page[offset..offset + header_size];
+    btree_header[0] = page_type;
+    // No freeblocks
+    btree_header[1..3].copy_from_slice(&0u16.to_be_bytes());
+    btree_header[3..5].copy_from_slice(&(cell_count as u16).to_be_bytes());
+    // 65536 is written as 0
+    btree_header[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    // No fragmented free bytes
+    btree_header[7] = 0;
+    if let Some(right_most_pointer) = right_most_pointer {
+        btree_header[8..12].copy_from_slice(&right_most_pointer.to_be_bytes());
+    }
+
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{encode_record, Value};
+    use sqlite_decoder::btree::{self, Cell};
+    use sqlite_types::Db;
+    use test_support::{check_db, create_db};
+
+    /// Re-encode the cells of a leaf page decoded by sqlite-decoder
+    fn reencode_leaf(db: &Db, page_number: u32) -> Page {
+        let btree = btree::decode_page(db, page_number).unwrap();
+        let payloads: Vec<(u64, Vec<u8>)> = btree
+            .cells
+            .iter()
+            .map(|cell| match cell {
+                Cell::TableBTreeLeafCell(cell) => (cell.rowid, cell.payload.payload().to_vec()),
+                cell => panic!("unexpected cell: {:?}", cell),
+            })
+            .collect();
+        let cells: Vec<TableLeafCell> = payloads
+            .iter()
+            .map(|(rowid, payload)| TableLeafCell {
+                rowid: *rowid,
+                payload_size: payload.len() as u64,
+                local_payload: payload,
+                first_overflow_page: None,
+            })
+            .collect();
+
+        encode_table_leaf(&db.header, page_number, &cells).unwrap()
+    }
+
+    #[test]
+    fn it_encodes_leaf_pages_like_sqlite() {
+        let db = create_db(
+            "
+            create table test (id integer primary key, value text);
+            insert into test values (1, 'a'), (2, 'bb'), (3, null), (10, 'c');
+            ",
+        );
+
+        // Page 1 holds the schema after the database header
+        let mut page = reencode_leaf(&db, 1);
+        page[..100].copy_from_slice(&db.pages[&1][..100]);
+        assert_eq!(page, db.pages[&1]);
+
+        assert_eq!(reencode_leaf(&db, 2), db.pages[&2]);
+    }
+
+    #[test]
+    fn it_encodes_interior_pages() {
+        let mut db = create_db(
+            "
+            pragma page_size = 512;
+            create table test (id integer primary key, value text);
+            ",
+        );
+        let header = db.header.clone();
+
+        // Three leaves of 10 rows under an interior root page
+        let records: Vec<Vec<u8>> = (1..=30)
+            .map(|i| {
+                let value = Value::Text(format!("value {}", i).into());
+                encode_record(&header.text_encoding, &[Value::Null, value])
+            })
+            .collect();
+        let mut interior_cells = Vec::new();
+        for (i, chunk) in records.chunks(10).enumerate() {
+            let page_number = 3 + i as u32;
+            let cells: Vec<TableLeafCell> = chunk
+                .iter()
+                .enumerate()
+                .map(|(j, record)| TableLeafCell {
+                    rowid: (i * 10 + j + 1) as u64,
+                    payload_size: record.len() as u64,
+                    local_payload: record,
+                    first_overflow_page: None,
+                })
+                .collect();
+            let page = encode_table_leaf(&header, page_number, &cells).unwrap();
+            db.pages.insert(page_number, page);
+
+            interior_cells.push(TableInteriorCell {
+                left_child_page: page_number,
+                rowid: cells.last().unwrap().rowid,
+            });
+        }
+        let right_most_pointer = interior_cells.pop().unwrap().left_child_page;
+        let page = encode_table_interior(&header, 2, &interior_cells, right_most_pointer).unwrap();
+        db.pages.insert(2, page);
+        db.header.db_size = 5;
+
+        let (conn, _file) = check_db(&db);
+        let values: Vec<(i64, String)> = conn
+            .prepare("select id, value from test order by id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values.len(), 30);
+        assert_eq!(values[29], (30, "value 30".to_owned()));
+    }
+
+    #[test]
+    fn it_rejects_cells_that_dont_fit() {
+        let db = create_db("pragma page_size = 512; create table test (value text);");
+        let record = encode_record(
+            &db.header.text_encoding,
+            &[Value::Blob(vec![0; 200].into())],
+        );
+        let cell = TableLeafCell {
+            rowid: 1,
+            payload_size: record.len() as u64,
+            local_payload: &record,
+            first_overflow_page: None,
+        };
+
+        assert!(encode_table_leaf(&db.header, 2, &[cell.clone(), cell.clone()]).is_ok());
+        assert!(encode_table_leaf(&db.header, 2, &[cell.clone(), cell.clone(), cell]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_inconsistent_cells() {
+        let db = create_db("pragma page_size = 512; create table test (value text);");
+        let record = encode_record(
+            &db.header.text_encoding,
+            &[Value::Blob(vec![0; 1000].into())],
+        );
+        let local_size = db
+            .header
+            .local_payload_size(true, record.len() as u64)
+            .unwrap();
+        assert!(local_size < record.len());
+
+        let cell = TableLeafCell {
+            rowid: 1,
+            payload_size: record.len() as u64,
+            local_payload: &record[..local_size],
+            first_overflow_page: Some(3),
+        };
+        assert!(encode_table_leaf(&db.header, 2, std::slice::from_ref(&cell)).is_ok());
+
+        // Local payload of the wrong size
+        let bad = TableLeafCell {
+            local_payload: &record[..local_size + 1],
+            ..cell.clone()
+        };
+        assert!(encode_table_leaf(&db.header, 2, &[bad]).is_err());
+        let bad = TableLeafCell {
+            local_payload: &record,
+            first_overflow_page: None,
+            ..cell.clone()
+        };
+        assert!(encode_table_leaf(&db.header, 2, &[bad]).is_err());
+
+        // Payload spilling without an overflow page
+        let bad = TableLeafCell {
+            first_overflow_page: None,
+            ..cell.clone()
+        };
+        assert!(encode_table_leaf(&db.header, 2, &[bad]).is_err());
+
+        // Payload fitting on the page with an overflow page
+        let bad = TableLeafCell {
+            payload_size: 10,
+            local_payload: &record[..10],
+            ..cell.clone()
+        };
+        assert!(encode_table_leaf(&db.header, 2, &[bad]).is_err());
+
+        // Payload fraction leaving no room for the payload
+        let mut header = db.header.clone();
+        header.leaf_payload_frac = 0;
+        assert!(encode_table_leaf(&header, 2, &[cell]).is_err());
+    }
+}
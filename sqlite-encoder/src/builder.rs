@@ -0,0 +1,337 @@
+//! Creation of new databases
+use crate::btree::{encode_empty_index_leaf, encode_table_leaf, is_empty_table_leaf};
+use crate::record::{encode_record, Value};
+use crate::table::{allocate_page, build_table};
+use crate::BoxError;
+use sqlite_types::{Db, DbHeader, Page, TextEncoding, SQLITE_3_37_2_VERSION};
+use std::collections::HashMap;
+
+/// Builds a new database: tables and indexes are registered in
+/// `sqlite_schema` when calling `finish`.
+/// The pages of the tables can be filled in the meantime, for instance with
+/// `table::build_table` on the root page returned by `create_table`. Indexes
+/// are left empty, so only tables without indexes can be filled.
+pub struct DbBuilder {
+    db: Db,
+    schema: Vec<SchemaEntry>,
+}
+
+struct SchemaEntry {
+    kind: &'static str,
+    name: String,
+    tbl_name: String,
+    root_page: u32,
+    sql: String,
+}
+
+impl DbBuilder {
+    /// Start an empty database with the header of `new_header`
+    pub fn new(page_size: u32, text_encoding: TextEncoding) -> Result<Self, BoxError> {
+        let header = new_header(page_size, text_encoding)?;
+
+        let first_page = empty_first_page(&header)?;
+        let db = Db {
+            header,
+            pages: HashMap::from([(1, first_page)]),
+        };
+
+        Ok(Self {
+            db,
+            schema: Vec::new(),
+        })
+    }
+
+    /// The database being built, to write the pages of the tables
+    pub fn db_mut(&mut self) -> &mut Db {
+        &mut self.db
+    }
+
+    /// Register a table created by `sql`, returns its empty root page
+    pub fn create_table(&mut self, name: &str, sql: &str) -> Result<u32, BoxError> {
+        self.check_name(name)?;
+
+        let root_page = allocate_page(&mut self.db);
+        let page = encode_table_leaf(&self.db.header, root_page, &[])?;
+        self.db.pages.insert(root_page, page);
+
+        self.push("table", name, name, root_page, sql);
+        Ok(root_page)
+    }
+
+    /// Register an index of `tbl_name` created by `sql`, returns its empty
+    /// root page
+    /// Indexes aren't filled, `finish` fails if rows were loaded in the
+    /// table.
+    pub fn create_index(&mut self, name: &str, tbl_name: &str, sql: &str) -> Result<u32, BoxError> {
+        self.check_name(name)?;
+        if !self
+            .schema
+            .iter()
+            .any(|entry| entry.kind == "table" && entry.name == tbl_name)
+        {
+            return Err(format!("no such table: {}", tbl_name).into());
+        }
+
+        let root_page = allocate_page(&mut self.db);
+        let page = encode_empty_index_leaf(&self.db.header, root_page)?;
+        self.db.pages.insert(root_page, page);
+
+        self.push("index", name, tbl_name, root_page, sql);
+        Ok(root_page)
+    }
+
+    /// Write the sqlite_schema table and return the database
+    pub fn finish(mut self) -> Result<Db, BoxError> {
+        for index in self.schema.iter().filter(|entry| entry.kind == "index") {
+            let table = self
+                .schema
+                .iter()
+                .find(|entry| entry.kind == "table" && entry.name == index.tbl_name)
+                .ok_or_else(|| format!("no such table: {}", index.tbl_name))?;
+            let is_empty = self
+                .db
+                .pages
+                .get(&table.root_page)
+                .is_some_and(|page| is_empty_table_leaf(page, table.root_page));
+            if !is_empty {
+                return Err(format!(
+                    "index {} of {} would be empty, indexed tables can't have rows",
+                    index.name, table.name
+                )
+                .into());
+            }
+        }
+
+        let enc = self.db.header.text_encoding.clone();
+        let rows: Vec<(i64, Vec<u8>)> = self
+            .schema
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let values = [
+                    Value::Text(entry.kind.into()),
+                    Value::Text(entry.name.as_str().into()),
+                    Value::Text(entry.tbl_name.as_str().into()),
+                    Value::Integer(entry.root_page as i64),
+                    Value::Text(entry.sql.as_str().into()),
+                ];
+                (i as i64 + 1, encode_record(&enc, &values))
+            })
+            .collect();
+        build_table(&mut self.db, 1, rows)?;
+
+        // Each schema change bumps the cookie
+        self.db.header.schema_cookie = self.schema.len() as u32;
+
+        let header = crate::db::encode_header(&self.db.header)?;
+        let first_page = self.db.pages.get_mut(&1).ok_or("missing page 1")?;
+        first_page[..100].copy_from_slice(&header);
+
+        Ok(self.db)
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), BoxError> {
+        if self
+            .schema
+            .iter()
+            .any(|entry| entry.name.eq_ignore_ascii_case(name))
+        {
+            return Err(format!("{} already exists", name).into());
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, kind: &'static str, name: &str, tbl_name: &str, root_page: u32, sql: &str) {
+        self.schema.push(SchemaEntry {
+            kind,
+            name: name.to_owned(),
+            tbl_name: tbl_name.to_owned(),
+            root_page,
+            sql: sql.to_owned(),
+        });
+    }
+}
+
+/// Header of a database newly created by sqlite3, with a single page, in
+/// rollback journal mode (file format versions 1)
+pub fn new_header(page_size: u32, text_encoding: TextEncoding) -> Result<DbHeader, BoxError> {
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+        return Err(format!("invalid page size: {}", page_size).into());
+    }
+
+    Ok(DbHeader {
+        page_size,
+        file_format_write_version: 1,
+        file_format_read_version: 1,
+        reserved_bytes: 0,
+        max_embedded_payload_frac: 64,
+        min_embedded_payload_frac: 32,
+        leaf_payload_frac: 32,
+        file_change_counter: 1,
+        db_size: 1,
+        page_num_first_freelist: 0,
+        page_count_freelist: 0,
+        schema_cookie: 0,
+        schema_format_number: 4,
+        default_page_cache_size: 0,
+        page_num_largest_root_btree: 0,
+        text_encoding,
+        user_version: 0,
+        vaccum_mode: 0,
+        app_id: 0,
+        version_valid_for: 1,
+        sqlite_version: SQLITE_3_37_2_VERSION,
+    })
+}
+
+/// Page 1 of a database without tables: the header followed by an empty
+/// sqlite_schema table
+pub fn empty_first_page(header: &DbHeader) -> Result<Page, BoxError> {
+    let mut page = encode_table_leaf(header, 1, &[])?;
+    page[..100].copy_from_slice(&crate::db::encode_header(header)?);
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::check_db;
+
+    #[test]
+    fn it_creates_empty_database() {
+        let db = DbBuilder::new(4096, TextEncoding::UTF8)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(db.header.db_size, 1);
+
+        let (conn, _file) = check_db(&db);
+        let count: u32 = conn
+            .query_row("select count(*) from sqlite_schema", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        conn.execute_batch("create table test (value text); insert into test values ('a');")
+            .unwrap();
+    }
+
+    #[test]
+    fn it_registers_tables_and_indexes() {
+        let mut builder = DbBuilder::new(512, TextEncoding::UTF16le).unwrap();
+        builder
+            .create_table(
+                "test",
+                "CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT)",
+            )
+            .unwrap();
+        builder
+            .create_index(
+                "test_value",
+                "test",
+                "CREATE INDEX test_value ON test (value)",
+            )
+            .unwrap();
+        assert!(builder
+            .create_index("other", "missing", "CREATE INDEX other ON missing (value)")
+            .is_err());
+        assert!(builder
+            .create_table("TEST", "CREATE TABLE TEST (a)")
+            .is_err());
+
+        // Enough tables for the schema to span several pages
+        let mut loaded = Vec::new();
+        for i in 0..50 {
+            let name = format!("table_{}", i);
+            let sql = format!("CREATE TABLE {} (id INTEGER PRIMARY KEY, value TEXT)", name);
+            let root_page = builder.create_table(&name, &sql).unwrap();
+            loaded.push((name, root_page));
+        }
+        let enc = builder.db_mut().header.text_encoding.clone();
+        for (i, (_, root_page)) in loaded.iter().enumerate() {
+            let rows = (1..=i as i64).map(|rowid| {
+                let value = Value::Text(format!("value {}", rowid).into());
+                (rowid, encode_record(&enc, &[Value::Null, value]))
+            });
+            build_table(builder.db_mut(), *root_page, rows).unwrap();
+        }
+
+        let db = builder.finish().unwrap();
+        let (conn, _file) = check_db(&db);
+
+        let encoding: String = conn
+            .query_row("pragma encoding", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(encoding, "UTF-16le");
+        for (i, (name, _)) in loaded.iter().enumerate() {
+            let count: usize = conn
+                .query_row(&format!("select count(*) from {}", name), [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(count, i);
+        }
+        let value: String = conn
+            .query_row("select value from table_49 where id = 42", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(value, "value 42");
+
+        conn.execute_batch("insert into test (value) values ('a'), ('b');")
+            .unwrap();
+        let id: i64 = conn
+            .query_row("select id from test where value = 'b'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(id, 2);
+    }
+
+    #[test]
+    fn it_rejects_rows_in_indexed_tables() {
+        let create = || {
+            let mut builder = DbBuilder::new(4096, TextEncoding::UTF8).unwrap();
+            let root_page = builder
+                .create_table(
+                    "test",
+                    "CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT)",
+                )
+                .unwrap();
+            builder
+                .create_index(
+                    "test_value",
+                    "test",
+                    "CREATE INDEX test_value ON test (value)",
+                )
+                .unwrap();
+            (builder, root_page)
+        };
+
+        let (mut builder, root_page) = create();
+        let enc = builder.db_mut().header.text_encoding.clone();
+        let rows = (1..=10).map(|rowid| {
+            let value = Value::Text(format!("value {}", rowid).into());
+            (rowid, encode_record(&enc, &[Value::Null, value]))
+        });
+        build_table(builder.db_mut(), root_page, rows).unwrap();
+        assert!(builder.finish().is_err());
+
+        // Empty indexed tables are valid, and SQLite fills their indexes
+        let (builder, _) = create();
+        let db = builder.finish().unwrap();
+        let (conn, _file) = check_db(&db);
+        conn.execute_batch("insert into test (value) values ('b'), ('a');")
+            .unwrap();
+        let integrity: String = conn
+            .query_row("pragma integrity_check", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(integrity, "ok");
+        let values: Vec<String> = conn
+            .prepare("select value from test indexed by test_value order by value")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values, ["a", "b"]);
+    }
+}
@@ -1,8 +1,7 @@
-use sqlite_types::{Db, DbHeader, TextEncoding, MAGIC_STRING};
+use crate::BoxError;
+use sqlite_types::{DbHeader, Pager, TextEncoding, MAGIC_STRING};
 use std::io::Write;
 
-type BoxError = Box<dyn std::error::Error + Send + Sync>;
-
 fn write_bytes(writer: &mut Vec<u8>, value: &[u8]) {
     writer.extend_from_slice(value);
 }
@@ -22,36 +21,50 @@ fn write_byte(writer: &mut Vec<u8>, value: u8) {
 pub fn encode_header(header: &DbHeader) -> Result<Vec<u8>, BoxError> {
     let mut buff = Vec::new();
 
-    write_header(&mut buff, &header).map_err(|err| format!("failed to encode header: {}", err))?;
+    write_header(&mut buff, header).map_err(|err| format!("failed to encode header: {}", err))?;
 
     Ok(buff)
 }
 
-pub fn encode(db: &Db) -> Result<Vec<u8>, BoxError> {
+/// Encode the database, pages are fetched from the pager in order
+pub fn encode<P: Pager + ?Sized>(pager: &P) -> Result<Vec<u8>, BoxError> {
     let mut buff = Vec::new();
+    encode_to(pager, &mut buff)?;
+    Ok(buff)
+}
 
-    let header_bytes = encode_header(&db.header)?;
-    let mut first_page = db.pages.get(&1).ok_or("missing page 1")?.clone();
-
-    (&mut first_page[0..100])
-        .write(&header_bytes)
-        .map_err(|err| format!("failed to write header: {}", err))?;
-    buff.write(&first_page)
+/// Encode the database into `writer`, one page at a time: pages are fetched
+/// from the pager in order and written as they come
+pub fn encode_to<P: Pager + ?Sized, W: Write>(pager: &P, writer: &mut W) -> Result<(), BoxError> {
+    let header = pager.header();
+
+    let header_bytes = encode_header(header)?;
+    let mut first_page = pager
+        .page(1)
+        .map_err(|err| format!("failed to read page 1: {}", err))?
+        .ok_or("missing page 1")?
+        .into_owned();
+
+    first_page[0..100].copy_from_slice(&header_bytes);
+    writer
+        .write_all(&first_page)
         .map_err(|err| format!("failed to write first page: {}", err))?;
 
-    for i in 1..db.header.db_size {
+    // Pages missing from the pager are written empty
+    let empty_page = vec![0u8; header.page_size as usize];
+    for i in 1..header.db_size {
         // Page number are 1 indexed and 1 is the db header
         let page_number = i + 1;
 
-        if let Some(page) = db.pages.get(&page_number) {
-            write_bytes(&mut buff, page);
-        } else {
-            // The page didn't exists, write an empty one
-            write_bytes(&mut buff, &vec![0u8; db.header.page_size as usize]);
-        }
+        let page = pager
+            .page(page_number)
+            .map_err(|err| format!("failed to read page {}: {}", page_number, err))?;
+        writer
+            .write_all(page.as_deref().unwrap_or(&empty_page))
+            .map_err(|err| format!("failed to write page {}: {}", page_number, err))?;
     }
 
-    Ok(buff)
+    Ok(())
 }
 
 fn write_header(writer: &mut Vec<u8>, header: &DbHeader) -> Result<(), BoxError> {
@@ -65,7 +78,7 @@ fn write_header(writer: &mut Vec<u8>, header: &DbHeader) -> Result<(), BoxError>
     write_u16(writer, page_size);
     write_byte(writer, header.file_format_write_version);
     write_byte(writer, header.file_format_read_version);
-    write_byte(writer, 0);
+    write_byte(writer, header.reserved_bytes);
     write_byte(writer, header.max_embedded_payload_frac);
     write_byte(writer, header.min_embedded_payload_frac);
     write_byte(writer, header.leaf_payload_frac);
@@ -101,3 +114,55 @@ fn write_text_encoding(writer: &mut Vec<u8>, enc: &TextEncoding) -> Result<(), B
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::{create_db_bytes, create_db_bytes_with_reserved_bytes};
+
+    #[test]
+    fn it_roundtrips_reserved_bytes() {
+        let bytes = create_db_bytes_with_reserved_bytes(
+            "
+            create table test (value text);
+            insert into test values ('a');
+            ",
+            32,
+        );
+        let db = sqlite_decoder::db::decode(&bytes).unwrap();
+        assert_eq!(db.header.reserved_bytes, 32);
+        assert_eq!(encode(&db).unwrap(), bytes);
+    }
+
+    #[test]
+    fn it_streams_pages_in_order() {
+        let bytes = create_db_bytes(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            insert into test values (randomblob(2000)), (randomblob(2000));
+            ",
+        );
+
+        let db = sqlite_decoder::db::decode(&bytes).unwrap();
+        assert!(db.header.db_size > 4);
+
+        // Each page is written on its own
+        struct Writes(Vec<Vec<u8>>);
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut writes = Writes(Vec::new());
+        encode_to(&db, &mut writes).unwrap();
+
+        assert_eq!(writes.0.len(), db.header.db_size as usize);
+        assert!(writes.0.iter().all(|page| page.len() == 512));
+        assert_eq!(writes.0.concat(), bytes);
+    }
+}
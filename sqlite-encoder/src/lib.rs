@@ -1,2 +1,10 @@
+pub mod btree;
+pub mod builder;
 pub mod db;
+pub mod ptrmap;
+pub mod record;
+pub mod table;
 pub mod wal;
+
+/// Error returned by the encoders, it can be sent across threads
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
@@ -0,0 +1,41 @@
+//! https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages
+use crate::BoxError;
+use sqlite_types::{DbHeader, Page, PtrmapEntry};
+use std::collections::HashMap;
+
+/// Encode the pointer-map pages of a database of `header.db_size` pages
+/// holding `entries`, keyed by the page they describe.
+/// Returns every pointer-map page with its page number, entries that aren't
+/// given are left unused.
+pub fn encode(
+    header: &DbHeader,
+    entries: &HashMap<u32, PtrmapEntry>,
+) -> Result<Vec<(u32, Page)>, BoxError> {
+    if !header.has_ptrmap() {
+        return Err("database isn't in auto-vacuum mode".into());
+    }
+
+    let mut pages: HashMap<u32, Page> = (2..=header.db_size)
+        .filter(|page_number| header.is_ptrmap_page(*page_number))
+        .map(|page_number| (page_number, vec![0u8; header.page_size as usize]))
+        .collect();
+
+    for (page_number, entry) in entries {
+        let (ptrmap_page, offset) = header
+            .ptrmap_location(*page_number)
+            .ok_or_else(|| format!("page {} can't have a pointer-map entry", page_number))?;
+        let page = pages.get_mut(&ptrmap_page).ok_or_else(|| {
+            format!(
+                "page {} is beyond the end of the database ({} pages)",
+                page_number, header.db_size
+            )
+        })?;
+
+        page[offset] = entry.page_type as u8;
+        page[offset + 1..offset + 5].copy_from_slice(&entry.parent.to_be_bytes());
+    }
+
+    let mut pages: Vec<(u32, Page)> = pages.into_iter().collect();
+    pages.sort_by_key(|(page_number, _)| *page_number);
+    Ok(pages)
+}
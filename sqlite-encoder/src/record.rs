@@ -0,0 +1,279 @@
+//! https://www.sqlite.org/fileformat.html#record_format
+use sqlite_types::TextEncoding;
+use std::borrow::Cow;
+
+/// A value to store in a record, the serial type is picked when encoding
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(Cow<'a, str>),
+    Blob(Cow<'a, [u8]>),
+}
+
+/// Encode `values` as a record: a header of serial types followed by the
+/// body with the values.
+/// Integers use the smallest serial type that fits them, 0 and 1 are
+/// stored without body (schema format 4).
+pub fn encode_record(enc: &TextEncoding, values: &[Value]) -> Vec<u8> {
+    let mut serial_types = Vec::new();
+    let mut body = Vec::new();
+
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(v) => {
+                let serial_type = integer_serial_type(*v);
+                let size = match serial_type {
+                    1..=4 => serial_type as usize,
+                    5 => 6,
+                    6 => 8,
+                    _ => 0,
+                };
+                body.extend_from_slice(&v.to_be_bytes()[8 - size..]);
+                serial_type
+            }
+            Value::Real(v) => {
+                body.extend(v.to_be_bytes());
+                7
+            }
+            Value::Text(v) => {
+                let bytes = encode_text(enc, v);
+                body.extend_from_slice(&bytes);
+                bytes.len() as u64 * 2 + 13
+            }
+            Value::Blob(v) => {
+                body.extend_from_slice(v);
+                v.len() as u64 * 2 + 12
+            }
+        };
+        write_varint(&mut serial_types, serial_type);
+    }
+
+    // The header size includes its own varint
+    let mut header_size = serial_types.len() as u64 + 1;
+    loop {
+        let size = serial_types.len() as u64 + varint_size(header_size) as u64;
+        if size == header_size {
+            break;
+        }
+        header_size = size;
+    }
+
+    let mut record = Vec::with_capacity(header_size as usize + body.len());
+    write_varint(&mut record, header_size);
+    record.extend(serial_types);
+    record.extend(body);
+    record
+}
+
+/// Serial type of an integer: the smallest of the 1, 2, 3, 4, 6 or 8 bytes
+/// integers, or 8 and 9 for the constants 0 and 1
+pub fn integer_serial_type(value: i64) -> u64 {
+    // Negative values take as many bytes as their complement
+    let u = if value < 0 { !value } else { value };
+
+    if value == 0 {
+        8
+    } else if value == 1 {
+        9
+    } else if u <= 0x7f {
+        1
+    } else if u <= 0x7fff {
+        2
+    } else if u <= 0x7fffff {
+        3
+    } else if u <= 0x7fffffff {
+        4
+    } else if u <= 0x7fffffffffff {
+        5
+    } else {
+        6
+    }
+}
+
+/// Append `value` as a varint of 1 to 9 bytes
+/// See https://www.sqlite.org/fileformat.html#varint
+pub fn write_varint(writer: &mut Vec<u8>, value: u64) {
+    // The ninth byte contributes all of its 8 bits
+    if value >> 56 != 0 {
+        for i in (1..=8).rev() {
+            writer.push(((value >> (i * 7 + 1)) & 0x7f) as u8 | 0x80);
+        }
+        writer.push(value as u8);
+        return;
+    }
+
+    let size = varint_size(value);
+    for i in (1..size).rev() {
+        writer.push(((value >> (i * 7)) & 0x7f) as u8 | 0x80);
+    }
+    writer.push((value & 0x7f) as u8);
+}
+
+/// Number of bytes of the varint encoding of `value`
+pub fn varint_size(value: u64) -> usize {
+    if value >> 56 != 0 {
+        return 9;
+    }
+
+    let mut size = 1;
+    let mut value = value >> 7;
+    while value != 0 {
+        size += 1;
+        value >>= 7;
+    }
+    size
+}
+
+/// Encode text in the database text encoding
+pub fn encode_text(enc: &TextEncoding, value: &str) -> Vec<u8> {
+    use TextEncoding::*;
+
+    match enc {
+        // SQLite defaults to UTF-8 when the encoding hasn't been set yet
+        Unspecified | UTF8 => value.as_bytes().to_vec(),
+        UTF16le => value.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        UTF16be => value.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlite_decoder::btree::{self, Cell};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn it_encodes_text() {
+        assert_eq!(encode_text(&TextEncoding::UTF8, "hé"), b"h\xc3\xa9");
+        assert_eq!(encode_text(&TextEncoding::UTF16le, "hé"), b"h\0\xe9\0");
+        assert_eq!(encode_text(&TextEncoding::UTF16be, "hé"), b"\0h\0\xe9");
+        assert_eq!(
+            encode_text(&TextEncoding::UTF16le, "🦀"),
+            b"\x3e\xd8\x80\xdd"
+        );
+    }
+
+    #[test]
+    fn it_writes_varints() {
+        let cases: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x81, 0x00]),
+            (0x3fff, &[0xff, 0x7f]),
+            (0x4000, &[0x81, 0x80, 0x00]),
+            (
+                0x00ff_ffff_ffff_ffff,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+            ),
+            (
+                0x0100_0000_0000_0000,
+                &[0x80, 0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+            ),
+            (u64::MAX, &[0xff; 9]),
+        ];
+
+        for (value, expected) in cases {
+            let mut buff = Vec::new();
+            write_varint(&mut buff, *value);
+            assert_eq!(&buff, expected, "varint {:#x}", value);
+            assert_eq!(varint_size(*value), expected.len());
+        }
+    }
+
+    #[test]
+    fn it_picks_the_smallest_integer_serial_type() {
+        let cases = [
+            (0, 8),
+            (1, 9),
+            (2, 1),
+            (-1, 1),
+            (127, 1),
+            (-128, 1),
+            (128, 2),
+            (-129, 2),
+            (32767, 2),
+            (32768, 3),
+            (-8388608, 3),
+            (8388608, 4),
+            (2147483647, 4),
+            (2147483648, 5),
+            (-140737488355328, 5),
+            (140737488355328, 6),
+            (i64::MIN, 6),
+            (i64::MAX, 6),
+        ];
+        for (value, serial_type) in cases {
+            assert_eq!(integer_serial_type(value), serial_type, "{}", value);
+        }
+    }
+
+    #[test]
+    fn it_encodes_records_like_sqlite() {
+        let file = NamedTempFile::new().unwrap();
+        {
+            let conn = rusqlite::Connection::open(file.path()).unwrap();
+            conn.execute_batch(
+                "
+                create table test (a, b, c, d, e, f, g);
+                insert into test values
+                    (null, 0, 1, -1, 1.5, 'text', x'0102'),
+                    (300, -70000, 2147483648, -9223372036854775808, '', x'', 9223372036854775807);
+                ",
+            )
+            .unwrap();
+            conn.execute(
+                "insert into test (a, b) values (?, ?)",
+                ("x".repeat(200), 8388607),
+            )
+            .unwrap();
+        }
+        let bytes = std::fs::read(file.path()).unwrap();
+        let db = sqlite_decoder::db::decode(&bytes).unwrap();
+        let btree = btree::decode_page(&db, 2).unwrap();
+
+        let rows: Vec<Vec<Value>> = vec![
+            vec![
+                Value::Null,
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Integer(-1),
+                Value::Real(1.5),
+                Value::Text("text".into()),
+                Value::Blob(vec![1, 2].into()),
+            ],
+            vec![
+                Value::Integer(300),
+                Value::Integer(-70000),
+                Value::Integer(2147483648),
+                Value::Integer(i64::MIN),
+                Value::Text("".into()),
+                Value::Blob(vec![].into()),
+                Value::Integer(i64::MAX),
+            ],
+            vec![
+                Value::Text("x".repeat(200).into()),
+                Value::Integer(8388607),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+            ],
+        ];
+
+        assert_eq!(btree.cells.len(), rows.len());
+        for (cell, values) in btree.cells.iter().zip(rows) {
+            let payload = match cell {
+                Cell::TableBTreeLeafCell(cell) => &cell.payload,
+                cell => panic!("unexpected cell: {:?}", cell),
+            };
+            assert_eq!(
+                encode_record(&TextEncoding::UTF8, &values),
+                payload.payload()
+            );
+        }
+    }
+}
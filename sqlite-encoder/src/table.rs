@@ -0,0 +1,421 @@
+//! Bulk loading of table B-trees, without going through SQLite
+use crate::btree::{
+    cell_space, encode_table_interior, encode_table_leaf, is_empty_table_leaf, TableInteriorCell,
+    TableLeafCell,
+};
+use crate::BoxError;
+use sqlite_types::{Db, PENDING_BYTE};
+
+/// Build the table B-tree rooted at `root_page` from `rows` of rowid and
+/// record, sorted by rowid.
+/// Leaves are filled one after the other and the interior pages are built
+/// on top of them, so every leaf is at the same depth. Records too large
+/// for a leaf are spilled to overflow pages.
+/// The other pages are appended to the database, `root_page` is
+/// overwritten and must be an empty table leaf, as created by
+/// `DbBuilder::create_table`.
+pub fn build_table<I>(db: &mut Db, root_page: u32, rows: I) -> Result<(), BoxError>
+where
+    I: IntoIterator<Item = (i64, Vec<u8>)>,
+{
+    if db.header.has_ptrmap() {
+        return Err("auto-vacuum databases aren't supported".into());
+    }
+    if root_page == 0 || root_page > db.header.db_size {
+        return Err(format!("root page {} isn't in the database", root_page).into());
+    }
+    // The pages of a filled table would be leaked
+    if !db
+        .pages
+        .get(&root_page)
+        .is_some_and(|page| is_empty_table_leaf(page, root_page))
+    {
+        return Err(format!("root page {} isn't an empty table", root_page).into());
+    }
+
+    // The new pages are never the first one, only the root can have less
+    // space because of the database header
+    let leaf_space = cell_space(&db.header, 2, false);
+    let interior_space = cell_space(&db.header, 2, true);
+    let root_leaf_space = cell_space(&db.header, root_page, false);
+    let root_interior_space = cell_space(&db.header, root_page, true);
+
+    let mut leaves: Vec<Child> = Vec::new();
+    let mut cells: Vec<LeafCell> = Vec::new();
+    let mut used = 0;
+    let mut last_rowid = None;
+
+    for (rowid, record) in rows {
+        if last_rowid.is_some_and(|last| rowid <= last) {
+            return Err(format!(
+                "rows aren't sorted by rowid: {} after {:?}",
+                rowid, last_rowid
+            )
+            .into());
+        }
+        last_rowid = Some(rowid);
+
+        let cell = LeafCell::new(db, rowid, record)?;
+        let size = cell.as_cell().size() + 2;
+        if used + size > leaf_space && !cells.is_empty() {
+            leaves.push(write_leaf(db, None, &cells)?);
+            cells.clear();
+            used = 0;
+        }
+        used += size;
+        cells.push(cell);
+    }
+
+    if leaves.is_empty() && used <= root_leaf_space {
+        write_leaf(db, Some(root_page), &cells)?;
+        return Ok(());
+    }
+    leaves.push(write_leaf(db, None, &cells)?);
+
+    // Like SQLite, a root without cells points to its only child when the
+    // child doesn't fit on the root
+    let mut children = leaves;
+    loop {
+        if group_children(children.clone(), root_interior_space).len() == 1 {
+            write_interior(db, Some(root_page), &children)?;
+            return Ok(());
+        }
+
+        let groups = group_children(children, interior_space);
+
+        children = groups
+            .iter()
+            .map(|group| write_interior(db, None, group))
+            .collect::<Result<_, _>>()?;
+    }
+}
+
+/// Page of the B-tree and the largest rowid under it
+#[derive(Debug, Clone, Copy)]
+struct Child {
+    page_number: u32,
+    rowid: i64,
+}
+
+/// Leaf cell whose payload was already spilled to overflow pages
+struct LeafCell {
+    rowid: i64,
+    payload_size: u64,
+    local_payload: Vec<u8>,
+    first_overflow_page: Option<u32>,
+}
+
+impl LeafCell {
+    fn new(db: &mut Db, rowid: i64, mut payload: Vec<u8>) -> Result<Self, BoxError> {
+        let payload_size = payload.len() as u64;
+        let local_size = db
+            .header
+            .local_payload_size(true, payload_size)
+            .ok_or("invalid payload fractions")?;
+        let first_overflow_page = if local_size < payload.len() {
+            Some(write_overflow(db, &payload[local_size..]))
+        } else {
+            None
+        };
+        payload.truncate(local_size);
+
+        Ok(Self {
+            rowid,
+            payload_size,
+            local_payload: payload,
+            first_overflow_page,
+        })
+    }
+
+    fn as_cell(&self) -> TableLeafCell<'_> {
+        TableLeafCell {
+            // Negative rowids are encoded as their two's complement
+            rowid: self.rowid as u64,
+            payload_size: self.payload_size,
+            local_payload: &self.local_payload,
+            first_overflow_page: self.first_overflow_page,
+        }
+    }
+}
+
+/// Append a new page to the database, skipping the page holding the
+/// pending byte which SQLite never uses
+pub(crate) fn allocate_page(db: &mut Db) -> u32 {
+    db.header.db_size += 1;
+    if db.header.db_size == PENDING_BYTE / db.header.page_size + 1 {
+        db.header.db_size += 1;
+    }
+    db.header.db_size
+}
+
+/// Write `overflow` to a chain of overflow pages, returns the first page
+fn write_overflow(db: &mut Db, overflow: &[u8]) -> u32 {
+    let page_size = db.header.page_size as usize;
+    let chunk_size = db.header.usable_size() as usize - 4;
+
+    let page_numbers: Vec<u32> = overflow
+        .chunks(chunk_size)
+        .map(|_| allocate_page(db))
+        .collect();
+    for (i, chunk) in overflow.chunks(chunk_size).enumerate() {
+        // Overflow pages start with the next page number, 0 on the last one
+        let next = page_numbers.get(i + 1).copied().unwrap_or(0);
+        let mut page = vec![0u8; page_size];
+        page[..4].copy_from_slice(&next.to_be_bytes());
+        page[4..4 + chunk.len()].copy_from_slice(chunk);
+        db.pages.insert(page_numbers[i], page);
+    }
+
+    page_numbers[0]
+}
+
+fn write_leaf(
+    db: &mut Db,
+    page_number: Option<u32>,
+    cells: &[LeafCell],
+) -> Result<Child, BoxError> {
+    let page_number = page_number.unwrap_or_else(|| allocate_page(db));
+    let rowid = cells.last().map_or(0, |cell| cell.rowid);
+    let cells: Vec<TableLeafCell> = cells.iter().map(LeafCell::as_cell).collect();
+    let page = encode_table_leaf(&db.header, page_number, &cells)?;
+    db.pages.insert(page_number, page);
+
+    Ok(Child { page_number, rowid })
+}
+
+fn write_interior(
+    db: &mut Db,
+    page_number: Option<u32>,
+    children: &[Child],
+) -> Result<Child, BoxError> {
+    let page_number = page_number.unwrap_or_else(|| allocate_page(db));
+    let (right_most, children) = children.split_last().ok_or("empty interior page")?;
+    let cells: Vec<TableInteriorCell> = children
+        .iter()
+        .map(|child| TableInteriorCell {
+            left_child_page: child.page_number,
+            rowid: child.rowid as u64,
+        })
+        .collect();
+    let page = encode_table_interior(&db.header, page_number, &cells, right_most.page_number)?;
+    db.pages.insert(page_number, page);
+
+    Ok(Child {
+        page_number,
+        rowid: right_most.rowid,
+    })
+}
+
+/// Split `children` into the children of the interior pages of the level
+/// above, filling each page. Every page but the last gets a cell per child,
+/// the last child being the right-most pointer.
+fn group_children(children: Vec<Child>, space: usize) -> Vec<Vec<Child>> {
+    let mut groups: Vec<Vec<Child>> = Vec::new();
+    let mut group: Vec<Child> = Vec::new();
+    let mut used = 0;
+
+    for child in children {
+        // The previous child becomes a cell when another one is added
+        let size = match group.last() {
+            Some(last) => {
+                TableInteriorCell {
+                    left_child_page: last.page_number,
+                    rowid: last.rowid as u64,
+                }
+                .size()
+                    + 2
+            }
+            None => 0,
+        };
+        if used + size > space {
+            groups.push(std::mem::take(&mut group));
+            used = 0;
+        } else {
+            used += size;
+        }
+        group.push(child);
+    }
+
+    // Interior pages need at least one cell
+    if group.len() == 1 && !groups.is_empty() {
+        let previous = groups.last_mut().unwrap();
+        group.insert(0, previous.pop().unwrap());
+    }
+    groups.push(group);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{encode_record, Value};
+    use std::ops::RangeInclusive;
+    use test_support::{check_db, create_db};
+
+    fn select_all(conn: &rusqlite::Connection) -> Vec<(i64, String)> {
+        conn.prepare("select id, value from test order by id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn value(i: i64) -> String {
+        // Every 100th value spills to overflow pages
+        let len = if i.unsigned_abs().is_multiple_of(100) {
+            3000
+        } else {
+            (i.unsigned_abs() % 50) as usize
+        };
+        format!("{}:{}", i, "x".repeat(len))
+    }
+
+    fn rows(db: &Db, range: RangeInclusive<i64>) -> impl Iterator<Item = (i64, Vec<u8>)> + '_ {
+        range.map(move |i| {
+            let rowid = i * 2;
+            let values = [Value::Null, Value::Text(value(rowid).into())];
+            (rowid, encode_record(&db.header.text_encoding, &values))
+        })
+    }
+
+    #[test]
+    fn it_builds_multi_level_table_btree() {
+        let mut db = create_db(
+            "
+            pragma page_size = 512;
+            create table test (id integer primary key, value text);
+            ",
+        );
+
+        let rows: Vec<_> = rows(&db, 1..=20000).collect();
+        build_table(&mut db, 2, rows).unwrap();
+        assert!(db.header.db_size > 1000);
+
+        let (conn, _file) = check_db(&db);
+        let depth = {
+            let mut depth = 1;
+            let mut page_number = 2;
+            loop {
+                let btree = sqlite_decoder::btree::decode_page(&db, page_number).unwrap();
+                match btree.header.right_most_pointer {
+                    Some(right_most_pointer) => page_number = right_most_pointer,
+                    None => break depth,
+                }
+                depth += 1;
+            }
+        };
+        assert!(depth >= 3);
+
+        let values = select_all(&conn);
+        assert_eq!(values.len(), 20000);
+        for (i, (rowid, value)) in values.into_iter().enumerate() {
+            let expected = (i as i64 + 1) * 2;
+            assert_eq!(rowid, expected);
+            assert_eq!(value, self::value(expected));
+        }
+    }
+
+    #[test]
+    fn it_builds_single_page_table() {
+        let mut db = create_db("create table test (id integer primary key, value text);");
+        let rows: Vec<_> = rows(&db, 1..=10).collect();
+        build_table(&mut db, 2, rows).unwrap();
+
+        let (conn, _file) = check_db(&db);
+        let count: u64 = conn
+            .query_row("select count(*) from test", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn it_rejects_unsorted_rows() {
+        let mut db = create_db("create table test (value text);");
+        let record = encode_record(&db.header.text_encoding, &[Value::Null]);
+        let rows = vec![(2, record.clone()), (1, record)];
+        assert!(build_table(&mut db, 2, rows).is_err());
+    }
+
+    #[test]
+    fn it_builds_table_with_negative_rowids() {
+        let mut db = create_db(
+            "
+            pragma page_size = 512;
+            create table test (id integer primary key, value text);
+            ",
+        );
+        let rows: Vec<_> = rows(&db, -500..=500).collect();
+        build_table(&mut db, 2, rows).unwrap();
+
+        let (conn, _file) = check_db(&db);
+        let values = select_all(&conn);
+        assert_eq!(values.len(), 1001);
+        assert_eq!(values[0], (-1000, value(-1000)));
+        assert_eq!(values[1000], (1000, value(1000)));
+        let value: String = conn
+            .query_row("select value from test where id = -2", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, self::value(-2));
+    }
+
+    #[test]
+    fn it_builds_table_rooted_on_the_first_page() {
+        let mut db = create_db(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            drop table test;
+            ",
+        );
+
+        // The first view only fits on a page without the database header
+        let views: Vec<_> = [400, 10, 10, 10]
+            .into_iter()
+            .enumerate()
+            .map(|(i, len)| {
+                let name = format!("view_{}", i);
+                let sql = format!("create view {} as select '{}'", name, "x".repeat(len));
+                let values = [
+                    Value::Text("view".into()),
+                    Value::Text(name.clone().into()),
+                    Value::Text(name.into()),
+                    Value::Integer(0),
+                    Value::Text(sql.into()),
+                ];
+                (
+                    i as i64 + 1,
+                    encode_record(&db.header.text_encoding, &values),
+                )
+            })
+            .collect();
+        assert!(views[0].1.len() > cell_space(&db.header, 1, false));
+        build_table(&mut db, 1, views).unwrap();
+
+        let (conn, _file) = check_db(&db);
+        let count: u64 = conn
+            .query_row("select count(*) from sqlite_schema", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+        let value: String = conn
+            .query_row("select * from view_0", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, "x".repeat(400));
+    }
+
+    #[test]
+    fn it_rejects_filled_root_page() {
+        let mut db = create_db(
+            "
+            create table test (id integer primary key, value text);
+            create index test_value on test (value);
+            ",
+        );
+        let rows: Vec<_> = rows(&db, 1..=10).collect();
+        build_table(&mut db, 2, rows.clone()).unwrap();
+
+        assert!(build_table(&mut db, 2, rows.clone()).is_err());
+        // Index root page
+        assert!(build_table(&mut db, 3, rows).is_err());
+    }
+}
@@ -1,23 +1,27 @@
-use sqlite_types::{checksum, Wal, WalFrame, WalFrameHeader, WalHeader, MAGIC_NUMBER_2};
+use crate::BoxError;
+use sqlite_types::{Wal, WalFrame, WalFrameHeader, WalHeader, MAGIC_NUMBER_1, MAGIC_NUMBER_2};
+use std::io::Write;
 
-type BoxError = Box<dyn std::error::Error>;
-
-pub fn encode(wal: Wal) -> Result<Vec<u8>, BoxError> {
+pub fn encode(wal: &Wal) -> Result<Vec<u8>, BoxError> {
     let mut buff = Vec::new();
+    encode_to(wal, &mut buff)?;
+    Ok(buff)
+}
 
+/// Encode the WAL into `writer`, frame by frame
+pub fn encode_to<W: Write>(wal: &Wal, writer: &mut W) -> Result<(), BoxError> {
     let (checksum_1, checksum_2) = wal.header.checksum();
 
-    write_wal_header(&mut buff, &wal.header, checksum_1, checksum_2)
+    write_wal_header(writer, &wal.header, checksum_1, checksum_2)
         .map_err(|err| format!("failed to encode header: {}", err))?;
 
     let mut checksum_1 = checksum_1;
     let mut checksum_2 = checksum_2;
 
     for frame in &wal.frames {
-        (checksum_1, checksum_2) = frame.header.checksum(checksum_1, checksum_2);
-        (checksum_1, checksum_2) = checksum_bytes(&frame.data, checksum_1, checksum_2);
+        (checksum_1, checksum_2) = wal.header.frame_checksum(frame, checksum_1, checksum_2);
 
-        write_wal_frame(&mut buff, frame, checksum_1, checksum_2).map_err(|err| {
+        write_wal_frame(writer, frame, checksum_1, checksum_2).map_err(|err| {
             format!(
                 "failed to write WAL frame #{}: {}",
                 frame.header.page_number, err
@@ -25,87 +29,115 @@ pub fn encode(wal: Wal) -> Result<Vec<u8>, BoxError> {
         })?;
     }
 
-    Ok(buff)
+    Ok(())
 }
 
-fn write_u32(writer: &mut Vec<u8>, value: u32) {
-    writer.extend(value.to_be_bytes());
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), BoxError> {
+    writer.write_all(&value.to_be_bytes())?;
+    Ok(())
 }
 
-pub fn write_wal_header(
-    writer: &mut Vec<u8>,
+pub fn write_wal_header<W: Write>(
+    writer: &mut W,
     header: &WalHeader,
     checksum_1: u32,
     checksum_2: u32,
 ) -> Result<(), BoxError> {
-    // MAGIC_NUMBER_2 uses big-endian, which we'll assume for now.
-    write_u32(writer, MAGIC_NUMBER_2);
-    write_u32(writer, header.file_format);
-    write_u32(writer, header.page_size);
-    write_u32(writer, header.checkpoint_seq);
-    write_u32(writer, header.salt_1);
-    write_u32(writer, header.salt_2);
-
-    write_u32(writer, checksum_1);
-    write_u32(writer, checksum_2);
+    // The magic number gives the byte order of the checksums
+    if header.magic_number != MAGIC_NUMBER_1 && header.magic_number != MAGIC_NUMBER_2 {
+        return Err(format!("invalid magic number: {:#x}", header.magic_number).into());
+    }
+    write_u32(writer, header.magic_number)?;
+    write_u32(writer, header.file_format)?;
+    write_u32(writer, header.page_size)?;
+    write_u32(writer, header.checkpoint_seq)?;
+    write_u32(writer, header.salt_1)?;
+    write_u32(writer, header.salt_2)?;
+
+    write_u32(writer, checksum_1)?;
+    write_u32(writer, checksum_2)?;
     Ok(())
 }
 
-pub fn write_wal_frame(
-    writer: &mut Vec<u8>,
+pub fn write_wal_frame<W: Write>(
+    writer: &mut W,
     frame: &WalFrame,
     checksum_1: u32,
     checksum_2: u32,
 ) -> Result<(), BoxError> {
     write_wal_frame_header(writer, &frame.header, checksum_1, checksum_2)?;
-    writer.extend(&frame.data);
+    writer.write_all(&frame.data)?;
     Ok(())
 }
 
-pub fn write_wal_frame_header(
-    writer: &mut Vec<u8>,
+pub fn write_wal_frame_header<W: Write>(
+    writer: &mut W,
     header: &WalFrameHeader,
     checksum_1: u32,
     checksum_2: u32,
 ) -> Result<(), BoxError> {
-    write_u32(writer, header.page_number);
-    write_u32(writer, header.db_size_after_commit);
-    write_u32(writer, header.salt_1);
-    write_u32(writer, header.salt_2);
-    write_u32(writer, checksum_1);
-    write_u32(writer, checksum_2);
+    write_u32(writer, header.page_number)?;
+    write_u32(writer, header.db_size_after_commit)?;
+    write_u32(writer, header.salt_1)?;
+    write_u32(writer, header.salt_2)?;
+    write_u32(writer, checksum_1)?;
+    write_u32(writer, checksum_2)?;
     Ok(())
 }
 
-fn checksum_bytes(bytes: &[u8], checksum_1: u32, checksum_2: u32) -> (u32, u32) {
-    let mut out = Vec::with_capacity(bytes.len() / 4);
-
-    for i in (0..bytes.len()).step_by(4) {
-        let v = u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
-        out.push(v);
-    }
-
-    checksum(&out, Some(checksum_1), Some(checksum_2))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_support::create_db_and_wal;
+
+    #[test]
+    fn it_reencodes_wal_in_native_byte_order() {
+        let (_, bytes) = create_db_and_wal(
+            "",
+            &["
+            create table test (value text);
+            insert into test values ('a'), ('b');
+            "],
+        );
+        let wal = sqlite_decoder::wal::decode(&bytes).unwrap();
+
+        let magic_number = if cfg!(target_endian = "little") {
+            MAGIC_NUMBER_1
+        } else {
+            MAGIC_NUMBER_2
+        };
+        assert_eq!(wal.header.magic_number, magic_number);
+        assert_eq!(
+            wal.header.checksum(),
+            (wal.header.checksum_1, wal.header.checksum_2)
+        );
+        assert_eq!(encode(&wal).unwrap(), bytes);
+    }
 
-    // #[test]
-    // fn test_calc_checksum_wal_frame() {
-    //     let header = WalHeader {
-    //         magic_number: MAGIC_NUMBER_2,
-    //         file_format: SUPPORTED_FILE_FORMAT,
-    //         page_size: 4096,
-    //         checkpoint_seq: 0,
-    //         salt_1: 1,
-    //         salt_2: 2,
-    //         checksum_1: 3,
-    //         checksum_2: 4,
-    //     };
-    //     let (s0, s1) = header.checksum();
-    //     println!("s0 {} s1 {}", s0, s1);
-    //     panic!()
-    // }
+    #[test]
+    fn it_switches_checksum_byte_order() {
+        let (_, bytes) = create_db_and_wal("", &["create table test (value text);"]);
+        let mut wal = sqlite_decoder::wal::decode(&bytes).unwrap();
+
+        for magic_number in [MAGIC_NUMBER_1, MAGIC_NUMBER_2] {
+            wal.header.magic_number = magic_number;
+            let encoded = encode(&wal).unwrap();
+            let decoded = sqlite_decoder::wal::decode(&encoded).unwrap();
+            assert_eq!(decoded.header.magic_number, magic_number);
+
+            // Frame checksums are chained from the header checksum
+            let mut checksum = decoded.header.checksum();
+            assert_eq!(
+                checksum,
+                (decoded.header.checksum_1, decoded.header.checksum_2)
+            );
+            for frame in &decoded.frames {
+                checksum = decoded.header.frame_checksum(frame, checksum.0, checksum.1);
+                assert_eq!(checksum, (frame.header.checksum_1, frame.header.checksum_2));
+            }
+        }
+
+        wal.header.magic_number = 0;
+        assert!(encode(&wal).is_err());
+    }
 }
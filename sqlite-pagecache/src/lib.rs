@@ -2,6 +2,13 @@
 
 // This is a workaround for rusqlite/rusqlite missing support for
 // `sqlite3_pcache_methods2` and/or not publishing on crates.io
+#[allow(
+    dead_code,
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    clippy::all
+)]
 mod ffi_bindgen;
 
 use ffi_bindgen as ffi;
@@ -16,7 +23,7 @@ pub enum InitState {
 
 type BoxError = Box<dyn std::error::Error>;
 
-pub fn make_page<'a>(page_size: usize, extra_size: usize) -> Page {
+pub fn make_page(page_size: usize, extra_size: usize) -> Page {
     let page = vec![0u8; page_size].into_boxed_slice();
     let extra = vec![0u8; extra_size].into_boxed_slice();
     Page {
@@ -137,6 +144,7 @@ pub fn build<'a, B: PageCacheBuiler<'a, T>, T: PageCache<'a> + 'a>(
     }))
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub fn register(pcache: *mut ffi::sqlite3_pcache_methods2) -> Result<(), BoxError> {
     log::trace!("register SQLITE_CONFIG_PCACHE2");
     let ret = unsafe { ffi::sqlite3_config(ffi::SQLITE_CONFIG_PCACHE2, pcache) };
@@ -200,7 +208,7 @@ mod pcache {
         extra_size: c_int,
         bpurgeable: c_int,
     ) -> *mut ffi::sqlite3_pcache {
-        let bpurgeable = if bpurgeable == 1 { true } else { false };
+        let bpurgeable = bpurgeable == 1;
         let pcache = Builder::create(page_size as usize, extra_size as usize, bpurgeable);
         let ctx = Box::new(Context {
             start_canary: 0xaaaabbbbccccdddd,
@@ -0,0 +1,562 @@
+//! Just enough of a `CREATE TABLE` parser to know how the rows of a table
+//! are stored: its columns, its primary key and whether it has a rowid.
+//! https://www.sqlite.org/lang_createtable.html
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// Names of the primary key columns, in key order
+    pub primary_key: Vec<String>,
+    pub without_rowid: bool,
+    /// Index of the `INTEGER PRIMARY KEY` column, which is an alias for the
+    /// rowid and stored as NULL in the records
+    pub rowid_alias: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    /// Declared type, empty when omitted
+    pub type_name: String,
+    /// Virtual generated columns aren't stored in the records
+    pub is_virtual: bool,
+    /// Literal `DEFAULT` value, `None` when omitted or when the default is an
+    /// expression, such as `CURRENT_TIMESTAMP` or `(1 + 1)`
+    pub default: Option<Literal>,
+}
+
+/// Literal value, see https://www.sqlite.org/syntax/literal-value.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl CreateTable {
+    /// Indices of the columns in the order their values are stored in the
+    /// records: the declaration order for rowid tables, the primary key
+    /// followed by the other columns for WITHOUT ROWID tables.
+    pub fn record_columns(&self) -> Vec<usize> {
+        let stored = (0..self.columns.len()).filter(|i| !self.columns[*i].is_virtual);
+        if !self.without_rowid {
+            return stored.collect();
+        }
+
+        let mut order: Vec<usize> = Vec::new();
+        for name in &self.primary_key {
+            if let Some(i) = self.column_index(name) {
+                if !order.contains(&i) {
+                    order.push(i);
+                }
+            }
+        }
+        let rest: Vec<usize> = stored.filter(|i| !order.contains(i)).collect();
+        order.extend(rest);
+        order
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Keyword or bare identifier
+    Word(String),
+    /// Quoted identifier or string literal
+    Quoted(String),
+    Punct(char),
+}
+
+impl Token {
+    fn is_word(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn name(&self) -> Option<&str> {
+        match self {
+            Token::Word(v) | Token::Quoted(v) => Some(v),
+            Token::Punct(_) => None,
+        }
+    }
+}
+
+/// Keywords starting a column constraint, ending the type name
+const COLUMN_CONSTRAINTS: &[&str] = &[
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+/// Keywords starting a table constraint
+const TABLE_CONSTRAINTS: &[&str] = &["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+/// Parse a `CREATE TABLE` statement, as stored in sqlite_schema
+/// Returns `None` for other statements and for `CREATE TABLE ... AS SELECT`.
+pub fn parse_create_table(sql: &str) -> Option<CreateTable> {
+    let tokens = tokenize(sql);
+    let mut iter = tokens.iter().peekable();
+
+    if !iter.next()?.is_word("CREATE") {
+        return None;
+    }
+    if iter.peek()?.is_word("TEMP") || iter.peek()?.is_word("TEMPORARY") {
+        iter.next();
+    }
+    if !iter.next()?.is_word("TABLE") {
+        return None;
+    }
+
+    // [IF NOT EXISTS] [schema.]name (
+    let mut token = iter.next()?;
+    if token.is_word("IF") {
+        if !iter.next()?.is_word("NOT") || !iter.next()?.is_word("EXISTS") {
+            return None;
+        }
+        token = iter.next()?;
+    }
+    let mut name = token.name()?.to_owned();
+    let mut token = iter.next()?;
+    if *token == Token::Punct('.') {
+        name = iter.next()?.name()?.to_owned();
+        token = iter.next()?;
+    }
+    if *token != Token::Punct('(') {
+        return None;
+    }
+
+    let rest: Vec<&Token> = iter.collect();
+    let (definitions, options) = split_group(&rest)?;
+
+    let mut columns = Vec::new();
+    let mut primary_key = Vec::new();
+    let mut column_pk_desc = false;
+    for definition in split_commas(definitions) {
+        let first = *definition.first()?;
+        if TABLE_CONSTRAINTS.iter().any(|k| first.is_word(k)) {
+            if let Some(names) = table_primary_key(&definition) {
+                primary_key = names;
+            }
+            continue;
+        }
+
+        let (column, pk, pk_desc) = parse_column(&definition)?;
+        if pk {
+            primary_key = vec![column.name.clone()];
+            column_pk_desc = pk_desc;
+        }
+        columns.push(column);
+    }
+
+    let without_rowid = options
+        .windows(2)
+        .any(|w| w[0].is_word("WITHOUT") && w[1].is_word("ROWID"));
+
+    let mut table = CreateTable {
+        name,
+        columns,
+        primary_key,
+        without_rowid,
+        rowid_alias: None,
+    };
+
+    // See https://www.sqlite.org/lang_createtable.html#rowid
+    if !table.without_rowid && !column_pk_desc && table.primary_key.len() == 1 {
+        table.rowid_alias = table
+            .column_index(&table.primary_key[0])
+            .filter(|i| table.columns[*i].type_name.eq_ignore_ascii_case("INTEGER"));
+    }
+
+    Some(table)
+}
+
+/// Returns the column, whether it's the primary key and whether the key is
+/// descending.
+fn parse_column(tokens: &[&Token]) -> Option<(Column, bool, bool)> {
+    let name = tokens.first()?.name()?.to_owned();
+
+    let mut type_name: Vec<String> = Vec::new();
+    let mut i = 1;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Word(w) if !COLUMN_CONSTRAINTS.iter().any(|k| w.eq_ignore_ascii_case(k)) => {
+                type_name.push(w.clone());
+            }
+            // Type arguments, such as VARCHAR(255)
+            Token::Punct('(') => {
+                let (group, _) = split_group(&tokens[i + 1..])?;
+                let args: Vec<String> = group
+                    .iter()
+                    .map(|t| match t {
+                        Token::Word(v) | Token::Quoted(v) => v.clone(),
+                        Token::Punct(c) => c.to_string(),
+                    })
+                    .collect();
+                let last = type_name.pop().unwrap_or_default();
+                type_name.push(format!("{}({})", last, args.join("")));
+                i += group.len() + 1;
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let constraints = top_level(&tokens[i..]);
+    let pk_at = constraints
+        .windows(2)
+        .position(|w| w[0].is_word("PRIMARY") && w[1].is_word("KEY"));
+    let pk_desc = pk_at
+        .and_then(|at| constraints.get(at + 2))
+        .map(|t| t.is_word("DESC"))
+        .unwrap_or(false);
+    let is_generated = constraints.iter().any(|t| t.is_word("AS"));
+    let is_stored = constraints.iter().any(|t| t.is_word("STORED"));
+    let default = tokens[i..]
+        .iter()
+        .position(|t| t.is_word("DEFAULT"))
+        .and_then(|at| parse_literal(&tokens[i + at + 1..]));
+
+    let column = Column {
+        name,
+        type_name: type_name.join(" "),
+        is_virtual: is_generated && !is_stored,
+        default,
+    };
+    Some((column, pk_at.is_some(), pk_desc))
+}
+
+/// Parse the literal at the start of the tokens, optionally signed for
+/// numbers
+fn parse_literal(tokens: &[&Token]) -> Option<Literal> {
+    let (sign, tokens) = match tokens.first()? {
+        Token::Punct(c @ ('-' | '+')) => (Some(*c), &tokens[1..]),
+        _ => (None, tokens),
+    };
+
+    if sign.is_none() {
+        match (tokens.first()?, tokens.get(1)) {
+            (Token::Quoted(v), _) => return Some(Literal::Text(v.clone())),
+            (Token::Word(x), Some(Token::Quoted(hex))) if x.eq_ignore_ascii_case("X") => {
+                return parse_blob(hex).map(Literal::Blob)
+            }
+            (Token::Word(w), _) if w.eq_ignore_ascii_case("NULL") => return Some(Literal::Null),
+            (Token::Word(w), _) if w.eq_ignore_ascii_case("TRUE") => {
+                return Some(Literal::Integer(1))
+            }
+            (Token::Word(w), _) if w.eq_ignore_ascii_case("FALSE") => {
+                return Some(Literal::Integer(0))
+            }
+            _ => {}
+        }
+    }
+
+    // Numbers are split on the decimal point and on the exponent sign
+    let mut number = String::new();
+    for token in tokens {
+        match token {
+            Token::Word(w)
+                if w.starts_with(|c: char| c.is_ascii_digit())
+                    && (number.is_empty() || number.ends_with(['.', '+', '-'])) =>
+            {
+                number.push_str(w)
+            }
+            Token::Punct('.') if !number.contains('.') => number.push('.'),
+            Token::Punct(c @ ('+' | '-')) if number.ends_with(['e', 'E']) => number.push(*c),
+            _ => break,
+        }
+    }
+    let literal = if let Some(hex) = number.strip_prefix("0x").or(number.strip_prefix("0X")) {
+        Literal::Integer(u64::from_str_radix(hex, 16).ok()? as i64)
+    } else if let Ok(v) = number.parse::<i64>() {
+        Literal::Integer(v)
+    } else {
+        // Including integers too large for 64 bits
+        Literal::Real(number.parse().ok()?)
+    };
+
+    Some(match (literal, sign) {
+        (Literal::Integer(v), Some('-')) => Literal::Integer(v.checked_neg()?),
+        (Literal::Real(v), Some('-')) => Literal::Real(-v),
+        (literal, _) => literal,
+    })
+}
+
+fn parse_blob(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Column names of a `PRIMARY KEY (...)` table constraint
+fn table_primary_key(tokens: &[&Token]) -> Option<Vec<String>> {
+    let at = tokens
+        .windows(2)
+        .position(|w| w[0].is_word("PRIMARY") && w[1].is_word("KEY"))?;
+    if tokens.get(at + 2) != Some(&&Token::Punct('(')) {
+        return None;
+    }
+
+    let (columns, _) = split_group(&tokens[at + 3..])?;
+    split_commas(columns)
+        .iter()
+        .map(|column| Some(column.first()?.name()?.to_owned()))
+        .collect()
+}
+
+/// Split the tokens following an opening parenthesis into the tokens up to
+/// the matching closing parenthesis, and the tokens after it.
+fn split_group<'a, 't>(tokens: &'a [&'t Token]) -> Option<(&'a [&'t Token], &'a [&'t Token])> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') if depth == 0 => return Some((&tokens[..i], &tokens[i + 1..])),
+            Token::Punct(')') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split tokens on the commas outside of parentheses
+fn split_commas<'t>(tokens: &[&'t Token]) -> Vec<Vec<&'t Token>> {
+    let mut out = vec![vec![]];
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                out.push(vec![]);
+                continue;
+            }
+            _ => {}
+        }
+        out.last_mut().unwrap().push(*token);
+    }
+    out.retain(|tokens| !tokens.is_empty());
+    out
+}
+
+/// Tokens outside of parentheses
+fn top_level<'t>(tokens: &[&'t Token]) -> Vec<&'t Token> {
+    let mut out = Vec::new();
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth -= 1,
+            token if depth == 0 => out.push(*token),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '\'' | '"' | '`' | '[' => {
+                let end = if c == '[' { ']' } else { c };
+                let mut value = String::new();
+                while let Some(c) = chars.next() {
+                    if c == end {
+                        // Quotes are escaped by doubling them
+                        if end != ']' && chars.peek() == Some(&end) {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    value.push(c);
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                let mut value = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if !(c.is_alphanumeric() || *c == '_' || *c == '$') {
+                        break;
+                    }
+                    value.push(*c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(value));
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq as pretty_eq;
+
+    fn column(name: &str, type_name: &str) -> Column {
+        Column {
+            name: name.to_owned(),
+            type_name: type_name.to_owned(),
+            is_virtual: false,
+            default: None,
+        }
+    }
+
+    #[test]
+    fn it_parses_rowid_table() {
+        let table = parse_create_table(
+            "CREATE TABLE IF NOT EXISTS main.\"my table\" (
+                id INTEGER PRIMARY KEY, -- the rowid
+                name VARCHAR(255) NOT NULL DEFAULT 'a, b',
+                [value] unsigned big int,
+                doubled int GENERATED ALWAYS AS (value * 2) VIRTUAL
+            )",
+        )
+        .unwrap();
+
+        pretty_eq!(
+            table,
+            CreateTable {
+                name: "my table".to_owned(),
+                columns: vec![
+                    column("id", "INTEGER"),
+                    Column {
+                        default: Some(Literal::Text("a, b".to_owned())),
+                        ..column("name", "VARCHAR(255)")
+                    },
+                    column("value", "unsigned big int"),
+                    Column {
+                        is_virtual: true,
+                        ..column("doubled", "int")
+                    },
+                ],
+                primary_key: vec!["id".to_owned()],
+                without_rowid: false,
+                rowid_alias: Some(0),
+            }
+        );
+        pretty_eq!(table.record_columns(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn it_parses_without_rowid_table() {
+        let table = parse_create_table(
+            "CREATE TABLE t (
+                a TEXT,
+                b INTEGER,
+                c BLOB,
+                CONSTRAINT pk PRIMARY KEY (c COLLATE nocase, a DESC)
+            ) WITHOUT ROWID",
+        )
+        .unwrap();
+
+        assert!(table.without_rowid);
+        pretty_eq!(table.primary_key, vec!["c".to_owned(), "a".to_owned()]);
+        pretty_eq!(table.rowid_alias, None);
+        pretty_eq!(table.record_columns(), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn it_detects_rowid_alias() {
+        let alias = |sql| parse_create_table(sql).unwrap().rowid_alias;
+
+        pretty_eq!(
+            alias("CREATE TABLE t (a integer, PRIMARY KEY (a DESC))"),
+            Some(0)
+        );
+        pretty_eq!(alias("CREATE TABLE t (a INTEGER PRIMARY KEY DESC)"), None);
+        pretty_eq!(alias("CREATE TABLE t (a INT PRIMARY KEY)"), None);
+        pretty_eq!(
+            alias("CREATE TABLE t (a INTEGER, b, PRIMARY KEY (a, b))"),
+            None
+        );
+        pretty_eq!(alias("CREATE TABLE t (a)"), None);
+    }
+
+    #[test]
+    fn it_parses_default_values() {
+        let table = parse_create_table(
+            "CREATE TABLE t (
+                a DEFAULT NULL,
+                b INT NOT NULL DEFAULT -42,
+                c DEFAULT +1.5e-3,
+                d DEFAULT 0x10 CHECK (d > 0),
+                e DEFAULT 'it''s',
+                f DEFAULT x'00fF',
+                g DEFAULT true,
+                h DEFAULT 9223372036854775808,
+                i DEFAULT (1 + 1),
+                j DEFAULT CURRENT_TIMESTAMP,
+                k
+            )",
+        )
+        .unwrap();
+
+        let defaults: Vec<Option<Literal>> = table.columns.into_iter().map(|c| c.default).collect();
+        pretty_eq!(
+            defaults,
+            vec![
+                Some(Literal::Null),
+                Some(Literal::Integer(-42)),
+                Some(Literal::Real(1.5e-3)),
+                Some(Literal::Integer(16)),
+                Some(Literal::Text("it's".to_owned())),
+                Some(Literal::Blob(vec![0, 255])),
+                Some(Literal::Integer(1)),
+                Some(Literal::Real(9223372036854775808.0)),
+                None,
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_ignores_other_statements() {
+        assert!(parse_create_table("CREATE INDEX i ON t (a)").is_none());
+        assert!(parse_create_table("CREATE TABLE t AS SELECT count(*) FROM u").is_none());
+        assert!(parse_create_table("CREATE TABLE t (a").is_none());
+    }
+}
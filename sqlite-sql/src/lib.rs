@@ -1,3 +1,6 @@
+mod create_table;
+pub use create_table::{parse_create_table, Column, CreateTable, Literal};
+
 #[cfg(test)]
 use pretty_assertions::assert_eq as pretty_eq;
 
@@ -75,15 +78,11 @@ pub fn split_statements(input: &str) -> Vec<String> {
             buffer.push(b);
 
             // Check if we didn't encourter a keywords
-            if b == ' ' {
-                if buffer.ends_with("BEGIN ") {
-                    state = State::InBegin;
-                }
+            if b == ' ' && buffer.ends_with("BEGIN ") {
+                state = State::InBegin;
             }
-            if b == '\n' {
-                if buffer.ends_with("BEGIN\n") {
-                    state = State::InBegin;
-                }
+            if b == '\n' && buffer.ends_with("BEGIN\n") {
+                state = State::InBegin;
             }
 
             if b == '\'' {
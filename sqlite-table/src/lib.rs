@@ -1,5 +1,6 @@
 use sqlite_decoder::btree;
-use std::collections::HashMap;
+use sqlite_types::Pager;
+use std::collections::{HashMap, HashSet};
 use std::ops::RangeInclusive;
 
 pub type Schemas = HashMap<String, Schema>;
@@ -9,6 +10,9 @@ type BoxError = Box<dyn std::error::Error>;
 pub enum Schema {
     Table(Table),
     Index(Index),
+    View(View),
+    Trigger(Trigger),
+    VirtualTable(VirtualTable),
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +20,19 @@ pub struct Table {
     pub name: String,
     pub sql: String,
     pub root_page: u32,
+    /// WITHOUT ROWID tables are stored in an index B-tree keyed by their
+    /// primary key, see https://www.sqlite.org/withoutrowid.html
+    pub without_rowid: bool,
+}
+
+/// A row of a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// `None` for WITHOUT ROWID tables
+    pub rowid: Option<u64>,
+    /// Values in the order of the columns of the table
+    /// Virtual generated columns aren't stored and are omitted.
+    pub values: Vec<btree::Record<'static>>,
 }
 
 #[derive(Debug)]
@@ -25,12 +42,15 @@ pub struct PageWithRowidRange {
 }
 
 impl Table {
-    pub fn list_pages(&self, db: &sqlite_types::Db) -> Result<Vec<PageWithRowidRange>, BoxError> {
-        let page = db.pages.get(&self.root_page).ok_or(format!(
-            "table root page ({}) not found in the database",
-            self.root_page
-        ))?;
-        let res = sqlite_decoder::btree::decode(&db.header.text_encoding, page)
+    pub fn list_pages<P: Pager + ?Sized>(
+        &self,
+        pager: &P,
+    ) -> Result<Vec<PageWithRowidRange>, BoxError> {
+        if self.without_rowid {
+            return Err(format!("table {} doesn't have rowids", self.name).into());
+        }
+
+        let res = sqlite_decoder::btree::decode_page(pager, self.root_page)
             .map_err(|err| format!("failed to decode B-tree: {}", err))?;
 
         let mut page_list = Vec::new();
@@ -54,12 +74,235 @@ impl Table {
                     // The table is small enough to fit on the root_page
                     // and doesn't have interior btree.
                 }
-                _ => unimplemented!(),
+                cell => return Err(format!("unexpected cell in table B-tree: {:?}", cell).into()),
             }
         }
 
         Ok(page_list)
     }
+
+    /// Scan the rows of the table, in rowid order or in primary key order for
+    /// WITHOUT ROWID tables.
+    pub fn rows<'a, P: Pager + ?Sized>(&self, pager: &'a P) -> Result<Rows<'a, P>, BoxError> {
+        let definition = sqlite_sql::parse_create_table(&self.sql);
+        let (value_positions, defaults, rowid_alias) = match &definition {
+            Some(definition) => {
+                // Values are returned in declaration order, without the
+                // virtual columns
+                let record_columns = definition.record_columns();
+                let position =
+                    |column: usize| record_columns.iter().filter(|c| **c < column).count();
+                let defaults = definition
+                    .columns
+                    .iter()
+                    .filter(|column| !column.is_virtual)
+                    .map(|column| default_value(column.default.as_ref()))
+                    .collect();
+                (
+                    Some(record_columns.iter().map(|c| position(*c)).collect()),
+                    defaults,
+                    definition.rowid_alias.map(position),
+                )
+            }
+            None if self.without_rowid => {
+                return Err(format!("failed to parse the definition of {}", self.name).into())
+            }
+            None => (None, Vec::new(), None),
+        };
+
+        let mut rows = Rows {
+            pager,
+            without_rowid: self.without_rowid,
+            value_positions,
+            defaults,
+            rowid_alias,
+            stack: Vec::new(),
+            visited: HashSet::new(),
+        };
+        rows.push(self.root_page)?;
+        Ok(rows)
+    }
+}
+
+/// Iterator over the rows of a table, walking its B-tree depth first
+pub struct Rows<'a, P: ?Sized> {
+    pager: &'a P,
+    without_rowid: bool,
+    /// Position in the row of each value of the records, `None` to keep the
+    /// record order
+    value_positions: Option<Vec<usize>>,
+    /// Values of the columns missing from records written before the
+    /// columns were added by `ALTER TABLE ADD COLUMN`, by position in the row
+    defaults: Vec<btree::Record<'static>>,
+    /// Position in the row of the rowid alias
+    rowid_alias: Option<usize>,
+    stack: Vec<RowsFrame<'a>>,
+    /// Pages of the B-tree already walked, a valid B-tree references each
+    /// page once
+    visited: HashSet<u32>,
+}
+
+struct RowsFrame<'a> {
+    btree: btree::Btree<'a>,
+    next_cell: usize,
+    /// Whether the left child of the next cell was already visited, index
+    /// interior cells hold a row too
+    visited_child: bool,
+    visited_right_most: bool,
+}
+
+impl<'a, P: Pager + ?Sized> Rows<'a, P> {
+    fn push(&mut self, page_number: u32) -> Result<(), BoxError> {
+        if !self.visited.insert(page_number) {
+            return Err(format!("page {} is referenced twice in the B-tree", page_number).into());
+        }
+
+        let btree = btree::decode_page(self.pager, page_number)
+            .map_err(|err| format!("failed to decode B-tree: {}", err))?;
+        self.stack.push(RowsFrame {
+            btree,
+            next_cell: 0,
+            visited_child: false,
+            visited_right_most: false,
+        });
+        Ok(())
+    }
+
+    fn next_row(&mut self) -> Result<Option<Row>, BoxError> {
+        use btree::Cell::*;
+
+        loop {
+            let frame = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            let cell = match frame.btree.cells.get(frame.next_cell) {
+                Some(cell) => cell,
+                None => {
+                    match frame.btree.header.right_most_pointer {
+                        Some(page_number) if !frame.visited_right_most => {
+                            frame.visited_right_most = true;
+                            self.push(page_number)?;
+                        }
+                        _ => {
+                            self.stack.pop();
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            match (cell, self.without_rowid) {
+                (TableBTreeInteriorCell(cell), false) => {
+                    let page_number = cell.left_child_page;
+                    frame.next_cell += 1;
+                    self.push(page_number)?;
+                }
+                (TableBTreeLeafCell(cell), false) => {
+                    let row = decode_row(
+                        self.value_positions.as_deref(),
+                        &self.defaults,
+                        self.rowid_alias,
+                        Some(cell.rowid),
+                        &cell.payload,
+                    );
+                    frame.next_cell += 1;
+                    return row.map(Some);
+                }
+                (IndexBTreeInteriorCell(cell), true) if !frame.visited_child => {
+                    let page_number = cell.left_child_page;
+                    frame.visited_child = true;
+                    self.push(page_number)?;
+                }
+                (IndexBTreeInteriorCell(cell), true) => {
+                    let row = decode_row(
+                        self.value_positions.as_deref(),
+                        &self.defaults,
+                        None,
+                        None,
+                        &cell.payload,
+                    );
+                    frame.visited_child = false;
+                    frame.next_cell += 1;
+                    return row.map(Some);
+                }
+                (IndexBTreeLeafCell(cell), true) => {
+                    let row = decode_row(
+                        self.value_positions.as_deref(),
+                        &self.defaults,
+                        None,
+                        None,
+                        &cell.payload,
+                    );
+                    frame.next_cell += 1;
+                    return row.map(Some);
+                }
+                (cell, _) => {
+                    return Err(format!("unexpected cell in table B-tree: {:?}", cell).into())
+                }
+            }
+        }
+    }
+}
+
+fn decode_row(
+    value_positions: Option<&[usize]>,
+    defaults: &[btree::Record<'static>],
+    rowid_alias: Option<usize>,
+    rowid: Option<u64>,
+    payload: &btree::RecordView,
+) -> Result<Row, BoxError> {
+    let records = payload.records()?;
+
+    let mut values = match value_positions {
+        Some(value_positions) => {
+            // Records are shorter than the rows when columns were added
+            // after they were written
+            let mut values = defaults.to_vec();
+            for (record, position) in records.into_iter().zip(value_positions) {
+                values[*position] = record.into_owned();
+            }
+            values
+        }
+        None => records.into_iter().map(|r| r.into_owned()).collect(),
+    };
+
+    // The rowid alias is stored as NULL in the records
+    if let (Some(rowid), Some(alias)) = (rowid, rowid_alias) {
+        if let Some(value) = values.get_mut(alias) {
+            *value = btree::Record::Int64(rowid as i64);
+        }
+    }
+
+    Ok(Row { rowid, values })
+}
+
+fn default_value(default: Option<&sqlite_sql::Literal>) -> btree::Record<'static> {
+    use sqlite_sql::Literal;
+
+    match default {
+        None | Some(Literal::Null) => btree::Record::Null,
+        Some(Literal::Integer(v)) => btree::Record::Int64(*v),
+        Some(Literal::Real(v)) => btree::Record::Float64(*v),
+        Some(Literal::Text(v)) => btree::Record::Text(v.clone().into()),
+        Some(Literal::Blob(v)) => btree::Record::Blob(v.clone().into()),
+    }
+}
+
+impl<'a, P: Pager + ?Sized> Iterator for Rows<'a, P> {
+    type Item = Result<Row, BoxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_row() {
+            Ok(row) => row.map(Ok),
+            Err(err) => {
+                // Stop after an error
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -70,17 +313,36 @@ pub struct Index {
     pub root_page: u32,
 }
 
+/// Views and triggers don't have a B-tree, their rootpage is 0
+#[derive(Debug)]
+pub struct View {
+    pub name: String,
+    pub sql: String,
+}
+
+#[derive(Debug)]
+pub struct Trigger {
+    pub name: String,
+    pub sql: String,
+    pub tbl_name: String,
+}
+
+/// Virtual tables don't have a B-tree either, the rows are held by the
+/// module, in shadow tables for FTS5 for example
+#[derive(Debug)]
+pub struct VirtualTable {
+    pub name: String,
+    pub sql: String,
+}
+
 pub fn find_table_by_root(rootpage: usize, schemas: &Schemas) -> Option<Table> {
     let mut table = None;
 
-    for (_, schema) in schemas {
-        match schema {
-            Schema::Table(schema) => {
-                if schema.root_page as usize == rootpage {
-                    table = Some(schema.clone());
-                }
+    for schema in schemas.values() {
+        if let Schema::Table(schema) = schema {
+            if schema.root_page as usize == rootpage {
+                table = Some(schema.clone());
             }
-            _ => {}
         }
     }
 
@@ -88,49 +350,359 @@ pub fn find_table_by_root(rootpage: usize, schemas: &Schemas) -> Option<Table> {
 }
 
 /// Decodes SQLite schema table
-/// The table is always located at page 1 (after the db3 header)
-pub fn decode_sqlite_schema(db: &sqlite_types::Db) -> Result<Schemas, BoxError> {
-    let root = 1;
-    let page = db.pages.get(&root).ok_or(format!(
-        "table root page ({}) not found in the database",
-        root
-    ))?;
-
-    let enc = &db.header.text_encoding;
-    let btree = btree::decode_first_page(enc, page)
-        .map_err(|err| format!("failed to decode B-tree: {}", err))?;
+/// The table is always rooted at page 1 (after the db3 header) and spans
+/// more pages once the schema grows.
+pub fn decode_sqlite_schema<P: Pager + ?Sized>(pager: &P) -> Result<Schemas, BoxError> {
+    let schema_table = Table {
+        name: "sqlite_schema".to_owned(),
+        sql: SQLITE_SCHEMA_SQL.to_owned(),
+        root_page: 1,
+        without_rowid: false,
+    };
 
     let mut schemas = HashMap::new();
 
-    for cell in btree.cells {
-        match cell {
-            btree::Cell::TableBTreeLeafCell(leaf) => {
-                let record_type = leaf.records[0].as_string();
-                let name = leaf.records[1].as_string();
-                let tbl_name = leaf.records[2].as_string();
-                let root_page = leaf.records[3].as_int() as u32;
-                let sql = leaf.records[4].as_string();
-
-                let schema = if record_type == "table" {
-                    Schema::Table(Table {
-                        name: name.clone(),
-                        root_page,
-                        sql,
-                    })
-                } else {
-                    Schema::Index(Index {
-                        name: name.clone(),
-                        root_page,
-                        sql,
-                        tbl_name,
-                    })
-                };
+    for row in schema_table.rows(pager)? {
+        let row = row?;
+        let record_type = schema_text(&row.values, 0)?;
+        let name = schema_text(&row.values, 1)?;
+        let tbl_name = schema_text(&row.values, 2)?;
+        let root_page = row
+            .values
+            .get(3)
+            .and_then(|v| v.as_int())
+            .ok_or("expected integer rootpage in sqlite_schema")? as u32;
+        // Automatic indexes don't have any SQL
+        let sql = schema_text(&row.values, 4).unwrap_or_default();
 
-                schemas.insert(name, schema);
+        let schema = match record_type.as_str() {
+            "table" if root_page == 0 => Schema::VirtualTable(VirtualTable {
+                name: name.clone(),
+                sql,
+            }),
+            "table" => {
+                // Tables whose definition can't be parsed are kept,
+                // `Table::rows` fails if it needs the definition
+                let without_rowid = match sqlite_sql::parse_create_table(&sql) {
+                    Some(definition) => definition.without_rowid,
+                    None => has_index_btree(pager, root_page),
+                };
+                Schema::Table(Table {
+                    name: name.clone(),
+                    root_page,
+                    sql,
+                    without_rowid,
+                })
             }
-            _ => unreachable!(),
-        }
+            "index" => Schema::Index(Index {
+                name: name.clone(),
+                root_page,
+                sql,
+                tbl_name,
+            }),
+            "view" => Schema::View(View {
+                name: name.clone(),
+                sql,
+            }),
+            "trigger" => Schema::Trigger(Trigger {
+                name: name.clone(),
+                sql,
+                tbl_name,
+            }),
+            _ => {
+                return Err(
+                    format!("unknown type {} of {} in sqlite_schema", record_type, name).into(),
+                )
+            }
+        };
+
+        schemas.insert(name, schema);
     }
 
     Ok(schemas)
 }
+
+/// Whether the B-tree rooted at `root_page` is an index B-tree, as the ones
+/// of WITHOUT ROWID tables
+fn has_index_btree<P: Pager + ?Sized>(pager: &P, root_page: u32) -> bool {
+    use btree::{PageContent, PageType};
+
+    btree::decode_page(pager, root_page).is_ok_and(|btree| {
+        matches!(
+            btree.header.page_type,
+            PageType::Leaf(PageContent::Index) | PageType::Interior(PageContent::Index)
+        )
+    })
+}
+
+const SQLITE_SCHEMA_SQL: &str =
+    "CREATE TABLE sqlite_schema(type text, name text, tbl_name text, rootpage integer, sql text)";
+
+fn schema_text(values: &[btree::Record], column: usize) -> Result<String, BoxError> {
+    match values.get(column) {
+        Some(btree::Record::Text(v)) => Ok(v.to_string()),
+        v => Err(format!(
+            "expected text in sqlite_schema column {}, given {:?}",
+            column, v
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::types::Value;
+    use sqlite_types::Db;
+    use test_support::create_db_and_connection;
+
+    fn to_value(record: &btree::Record) -> Value {
+        match record {
+            btree::Record::Null => Value::Null,
+            btree::Record::Float64(v) => Value::Real(*v),
+            btree::Record::Blob(v) => Value::Blob(v.to_vec()),
+            btree::Record::Text(v) => Value::Text(v.to_string()),
+            v => Value::Integer(v.as_int().unwrap()),
+        }
+    }
+
+    fn select_all(conn: &rusqlite::Connection, sql: &str) -> Vec<Vec<Value>> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let column_count = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..column_count)
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    }
+
+    fn table(db: &Db, name: &str) -> Table {
+        match decode_sqlite_schema(db).unwrap().remove(name) {
+            Some(Schema::Table(table)) => table,
+            schema => panic!("expected table {}, given {:?}", name, schema),
+        }
+    }
+
+    #[test]
+    fn it_scans_rowid_tables() {
+        let (db, conn, _file) = create_db_and_connection(
+            "
+            pragma page_size = 512;
+            create table test (value text, id integer primary key, n);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 500)
+            insert into test (id, value, n) select i * 3, printf('%.*c', i % 50, 'x'), i / 2.0 from n;
+            ",
+        );
+        let table = table(&db, "test");
+        assert!(!table.without_rowid);
+        // The B-tree has interior pages
+        assert!(!table.list_pages(&db).unwrap().is_empty());
+
+        let rows = table
+            .rows(&db)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 500);
+        assert_eq!(rows[0].rowid, Some(3));
+
+        let values: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|row| row.values.iter().map(to_value).collect())
+            .collect();
+        assert_eq!(values, select_all(&conn, "select * from test order by id"));
+    }
+
+    #[test]
+    fn it_scans_without_rowid_tables() {
+        let (db, conn, _file) = create_db_and_connection(
+            "
+            pragma page_size = 512;
+            create table test (
+                value text,
+                b integer,
+                a text,
+                primary key (a, b)
+            ) without rowid;
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 500)
+            insert into test select printf('%.*c', i % 80, 'x'), i, printf('key%d', i % 7) from n;
+            ",
+        );
+        let table = table(&db, "test");
+        assert!(table.without_rowid);
+        assert!(table.list_pages(&db).is_err());
+
+        let rows = table
+            .rows(&db)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 500);
+        assert!(rows.iter().all(|row| row.rowid.is_none()));
+
+        let values: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|row| row.values.iter().map(to_value).collect())
+            .collect();
+        assert_eq!(
+            values,
+            select_all(&conn, "select * from test order by a, b")
+        );
+    }
+
+    #[test]
+    fn it_applies_defaults_of_added_columns() {
+        let (db, conn, _file) = create_db_and_connection(
+            "
+            create table test (id integer primary key, value text);
+            insert into test (value) values ('a'), ('b');
+            alter table test add column n integer default -3;
+            alter table test add column s text default 'none';
+            alter table test add column f default 1.5;
+            alter table test add column b blob default x'0102';
+            alter table test add column x;
+            insert into test values (10, 'c', 1, 's', 2.5, x'03', 'x');
+            ",
+        );
+
+        let rows = table(&db, "test")
+            .rows(&db)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let values: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|row| row.values.iter().map(to_value).collect())
+            .collect();
+        assert_eq!(values, select_all(&conn, "select * from test order by id"));
+        assert_eq!(values[0][2], Value::Integer(-3));
+    }
+
+    #[test]
+    fn it_decodes_views_and_triggers() {
+        let (db, _conn, _file) = create_db_and_connection(
+            "
+            create table test (id integer primary key, value text);
+            create view test_values as select value from test;
+            create trigger test_insert after insert on test begin select 1; end;
+            create virtual table search using fts5(body);
+            ",
+        );
+
+        let schemas = decode_sqlite_schema(&db).unwrap();
+        assert!(matches!(
+            schemas.get("test_values"),
+            Some(Schema::View(View { sql, .. })) if sql.starts_with("CREATE VIEW")
+        ));
+        assert!(matches!(
+            schemas.get("test_insert"),
+            Some(Schema::Trigger(Trigger { tbl_name, .. })) if tbl_name == "test"
+        ));
+        assert!(matches!(
+            schemas.get("search"),
+            Some(Schema::VirtualTable(VirtualTable { sql, .. }))
+                if sql.starts_with("CREATE VIRTUAL TABLE")
+        ));
+        // The shadow tables of the virtual table are plain tables
+        assert!(matches!(schemas.get("search_data"), Some(Schema::Table(_))));
+    }
+
+    #[test]
+    fn it_keeps_tables_with_unparsable_definitions() {
+        let (db, _conn, _file) = create_db_and_connection(
+            "
+            create table test (value text);
+            insert into test values ('a');
+            create table keys (key text primary key, value text) without rowid;
+            insert into keys values ('k', 'b');
+            pragma writable_schema = on;
+            update sqlite_schema set sql = 'CREATE TABLE ' || name || ' unparsable'
+            where type = 'table';
+            ",
+        );
+
+        let test = table(&db, "test");
+        assert!(!test.without_rowid);
+        let rows = test
+            .rows(&db)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![Row {
+                rowid: Some(1),
+                values: vec![btree::Record::Text("a".into())]
+            }]
+        );
+
+        let keys = table(&db, "keys");
+        assert!(keys.without_rowid);
+        assert!(keys.rows(&db).is_err());
+    }
+
+    #[test]
+    fn it_rejects_pages_referenced_twice() {
+        let (mut db, _conn, _file) = create_db_and_connection(
+            "
+            pragma page_size = 512;
+            create table test (id integer primary key, value text);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 500)
+            insert into test select i, printf('%.*c', 20, 'x') from n;
+            ",
+        );
+        let test = table(&db, "test");
+        let cell_offset = |db: &Db, n: usize| {
+            let page = &db.pages[&test.root_page];
+            u16::from_be_bytes([page[12 + n * 2], page[13 + n * 2]]) as usize
+        };
+        let (first, second) = (cell_offset(&db, 0), cell_offset(&db, 1));
+
+        // Left child of the second cell pointing to the one of the first
+        let original = db.pages[&test.root_page].clone();
+        let page = db.pages.get_mut(&test.root_page).unwrap();
+        page.copy_within(first..first + 4, second);
+        assert!(test.rows(&db).unwrap().any(|row| row.is_err()));
+
+        // Left child of the first cell pointing back to the root
+        let page = db.pages.get_mut(&test.root_page).unwrap();
+        *page = original;
+        page[first..first + 4].copy_from_slice(&test.root_page.to_be_bytes());
+        let err = test.rows(&db).unwrap().find_map(Result::err).unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("page {} is referenced twice in the B-tree", test.root_page)
+        );
+    }
+
+    #[test]
+    fn it_decodes_multi_page_schema() {
+        let mut sql = String::from("pragma page_size = 512;");
+        for i in 0..100 {
+            sql += &format!("create table t{} (id integer primary key, value text);", i);
+            sql += &format!("create index t{}_value on t{} (value);", i, i);
+        }
+        // Long enough to overflow
+        let columns: Vec<String> = (0..200).map(|i| format!("column_{} text", i)).collect();
+        sql += &format!("create table wide ({});", columns.join(", "));
+        let (db, conn, _file) = create_db_and_connection(&sql);
+
+        let schemas = decode_sqlite_schema(&db).unwrap();
+        let count: usize = conn
+            .query_row("select count(*) from sqlite_schema", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 201);
+        assert_eq!(schemas.len(), count);
+
+        let wide = table(&db, "wide");
+        assert!(wide.sql.len() > 512);
+        assert!(wide.sql.ends_with("column_199 text)"));
+        assert!(matches!(
+            schemas.get("t99_value"),
+            Some(Schema::Index(Index { tbl_name, .. })) if tbl_name == "t99"
+        ));
+    }
+}
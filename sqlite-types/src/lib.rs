@@ -1,10 +1,15 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
 
 pub const MAGIC_NUMBER_1: u32 = 0x377f0682;
 pub const MAGIC_NUMBER_2: u32 = 0x377f0683;
 pub const SUPPORTED_FILE_FORMAT: u32 = 3007000;
 pub const MAGIC_STRING: &[u8] = b"SQLite format 3\0";
 pub const SQLITE_3_37_2_VERSION: u32 = 3038002;
+/// Offset of the byte used for locking, the page containing it is unused
+pub const PENDING_BYTE: u32 = 0x40000000;
 
 #[derive(Debug, Clone)]
 pub enum TextEncoding {
@@ -22,6 +27,50 @@ pub struct Db {
     pub pages: HashMap<u32, Page>,
 }
 
+/// Source of the number of pages of a database
+/// See https://www.sqlite.org/fileformat.html#in_header_database_size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbSizeSource {
+    /// The in-header database size
+    Header,
+    /// The length of the file, because the in-header size wasn't valid or
+    /// was larger than the file. Holds the in-header size.
+    FileLength { header_db_size: u32 },
+}
+
+/// Access to the pages of a database, fetched on demand.
+/// Implementations can keep the pages in memory or read them from a file,
+/// see `sqlite_decoder::pager`.
+pub trait Pager {
+    /// Header of the database, as found at the start of page 1
+    fn header(&self) -> &DbHeader;
+
+    /// Returns page `page_number` (1 indexed), or `None` when the page isn't
+    /// in the database.
+    /// Page 1 includes the 100 bytes of the database header.
+    fn page(&self, page_number: u32) -> io::Result<Option<Cow<'_, [u8]>>>;
+
+    /// Number of pages in the database
+    fn page_count(&self) -> u32;
+}
+
+impl Pager for Db {
+    fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    fn page(&self, page_number: u32) -> io::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self
+            .pages
+            .get(&page_number)
+            .map(|page| Cow::Borrowed(page.as_slice())))
+    }
+
+    fn page_count(&self) -> u32 {
+        self.header.db_size
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DbHeader {
     /// Page size of the database
@@ -30,6 +79,9 @@ pub struct DbHeader {
     pub page_size: u32,
     pub file_format_write_version: u8,
     pub file_format_read_version: u8,
+    /// Bytes reserved at the end of each page, used by extensions such as
+    /// checksums or encryption
+    pub reserved_bytes: u8,
     pub max_embedded_payload_frac: u8,
     pub min_embedded_payload_frac: u8,
     pub leaf_payload_frac: u8,
@@ -49,10 +101,182 @@ pub struct DbHeader {
     pub sqlite_version: u32,
 }
 
+impl DbHeader {
+    /// Whether the in-header database size can be trusted. Versions of
+    /// SQLite before 3.7.0 don't maintain it, which is detected by the
+    /// version-valid-for number not matching the change counter.
+    pub fn is_db_size_valid(&self) -> bool {
+        self.db_size != 0 && self.file_change_counter == self.version_valid_for
+    }
+
+    /// Number of pages of a database whose file holds `file_page_count`
+    /// whole pages, and where it was taken from.
+    /// A valid in-header size is used unless the file is too short for it,
+    /// extra pages at the end of the file are ignored.
+    pub fn resolve_db_size(&self, file_page_count: u32) -> (u32, DbSizeSource) {
+        if self.is_db_size_valid() && self.db_size <= file_page_count {
+            (self.db_size, DbSizeSource::Header)
+        } else {
+            let source = DbSizeSource::FileLength {
+                header_db_size: self.db_size,
+            };
+            (file_page_count, source)
+        }
+    }
+
+    /// Page size without the reserved bytes, the space available to the
+    /// B-tree, overflow, freelist and pointer-map pages.
+    pub fn usable_size(&self) -> u32 {
+        self.page_size.saturating_sub(self.reserved_bytes as u32)
+    }
+
+    /// Number of bytes of a payload stored in the cell itself, the remaining
+    /// bytes are spilled to overflow pages. Table leaf cells keep more of
+    /// their payload than index cells.
+    /// Returns `None` if the usable size and the payload fractions leave no
+    /// room for the payload.
+    /// See https://www.sqlite.org/fileformat.html#cellformat
+    pub fn local_payload_size(&self, table_leaf: bool, payload_size: u64) -> Option<usize> {
+        let usable_size = self.usable_size() as u64;
+        let fraction =
+            |frac: u8| (usable_size.checked_sub(12)? * frac as u64 / 255).checked_sub(23);
+        let (max_local, min_local) = if table_leaf {
+            (
+                usable_size.checked_sub(35)?,
+                fraction(self.leaf_payload_frac)?,
+            )
+        } else {
+            (
+                fraction(self.max_embedded_payload_frac)?,
+                fraction(self.min_embedded_payload_frac)?,
+            )
+        };
+
+        if payload_size <= max_local {
+            return Some(payload_size as usize);
+        }
+        if min_local > max_local {
+            return None;
+        }
+        let size = min_local + (payload_size - min_local) % (usable_size - 4);
+        let size = if size <= max_local { size } else { min_local };
+        Some(size as usize)
+    }
+
+    /// Whether the database is in auto-vacuum or incremental-vacuum mode,
+    /// and therefore contains pointer-map pages.
+    pub fn has_ptrmap(&self) -> bool {
+        self.page_num_largest_root_btree != 0
+    }
+
+    /// Whether `page_number` is a pointer-map page
+    pub fn is_ptrmap_page(&self, page_number: u32) -> bool {
+        self.has_ptrmap() && page_number >= 2 && self.ptrmap_page(page_number) == page_number
+    }
+
+    /// Location of the pointer-map entry of `page_number`: the pointer-map
+    /// page holding it and the offset of the entry on that page.
+    /// Returns `None` for pages without entry.
+    pub fn ptrmap_location(&self, page_number: u32) -> Option<(u32, usize)> {
+        if !self.has_ptrmap() || page_number < 3 {
+            return None;
+        }
+
+        let ptrmap_page = self.ptrmap_page(page_number);
+        if ptrmap_page >= page_number {
+            return None;
+        }
+        Some((ptrmap_page, 5 * (page_number - ptrmap_page - 1) as usize))
+    }
+
+    /// Pointer-map page covering `page_number`, see `ptrmapPageno` in
+    /// SQLite's btree.c
+    fn ptrmap_page(&self, page_number: u32) -> u32 {
+        let pages_per_ptrmap = self.usable_size() / 5 + 1;
+        let ptrmap_page = (page_number - 2) / pages_per_ptrmap * pages_per_ptrmap + 2;
+
+        // The page containing the lock byte is never used
+        let pending_byte_page = PENDING_BYTE / self.page_size + 1;
+        if ptrmap_page == pending_byte_page {
+            ptrmap_page + 1
+        } else {
+            ptrmap_page
+        }
+    }
+}
+
+/// Type of page described by a pointer-map entry
+/// See https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtrmapType {
+    /// B-tree root page, without parent
+    RootPage = 1,
+    /// Page on the freelist, without parent
+    FreePage = 2,
+    /// First page of an overflow chain, the parent is the B-tree page
+    /// holding the cell
+    Overflow1 = 3,
+    /// Other pages of an overflow chain, the parent is the previous
+    /// overflow page
+    Overflow2 = 4,
+    /// Non-root B-tree page, the parent is the parent B-tree page
+    Btree = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtrmapEntry {
+    pub page_type: PtrmapType,
+    pub parent: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Wal {
     pub header: WalHeader,
+    /// Frames of the valid prefix of the WAL
+    /// Includes the frames after the last commit frame, which SQLite ignores:
+    /// its mxFrame ends at the last commit, see `committed_frames`.
     pub frames: Vec<WalFrame>,
+    /// Why the frames after `frames` were ignored when decoding the WAL
+    pub stop_reason: WalStopReason,
+}
+
+/// Reason decoding a WAL stopped, like SQLite does when recovering it.
+/// Frames index from 0, which is also the number of valid frames before
+/// them.
+/// See https://www.sqlite.org/fileformat.html#wal_file_format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalStopReason {
+    /// No bytes left for another frame
+    #[default]
+    EndOfFile,
+    /// The header checksum is invalid, none of the frames are valid
+    BadHeaderChecksum,
+    /// The salts of the frame don't match the header, it's left from a
+    /// previous generation of the WAL
+    SaltMismatch { frame: usize },
+    /// The checksum of the frame doesn't match the checksum computed
+    /// over the header and all the frames up to it
+    BadChecksum { frame: usize },
+    /// The frame is for page 0, page numbers start at 1
+    BadPageNumber { frame: usize },
+}
+
+impl fmt::Display for WalStopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalStopReason::EndOfFile => write!(f, "end of file"),
+            WalStopReason::BadHeaderChecksum => write!(f, "invalid header checksum"),
+            WalStopReason::SaltMismatch { frame } => {
+                write!(f, "salts of frame {} don't match the header", frame)
+            }
+            WalStopReason::BadChecksum { frame } => {
+                write!(f, "invalid checksum for frame {}", frame)
+            }
+            WalStopReason::BadPageNumber { frame } => {
+                write!(f, "frame {} is for page 0", frame)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -101,35 +325,133 @@ impl Wal {
 
         self
     }
+
+    /// Group the frames into transactions, each ending with a commit frame
+    pub fn transactions(&self) -> WalTransactions<'_> {
+        let mut committed = Vec::new();
+        let mut start = 0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.header.is_commit() {
+                committed.push(&self.frames[start..=i]);
+                start = i + 1;
+            }
+        }
+
+        let uncommitted = if start < self.frames.len() {
+            Some(&self.frames[start..])
+        } else {
+            None
+        };
+        WalTransactions {
+            committed,
+            uncommitted,
+        }
+    }
+
+    /// Frames of the committed transactions, without the uncommitted tail
+    pub fn committed_frames(&self) -> &[WalFrame] {
+        let end = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.header.is_commit())
+            .map_or(0, |i| i + 1);
+        &self.frames[..end]
+    }
+
+    /// Number of pages of the database after the last committed
+    /// transaction, `None` without commit frames
+    pub fn last_commit_db_size(&self) -> Option<u32> {
+        self.frames
+            .iter()
+            .rev()
+            .find(|frame| frame.header.is_commit())
+            .map(|frame| frame.header.db_size_after_commit)
+    }
+}
+
+/// Frames of a WAL grouped into transactions
+#[derive(Debug, Clone)]
+pub struct WalTransactions<'a> {
+    /// Transactions in WAL order, the last frame of each is a commit frame
+    pub committed: Vec<&'a [WalFrame]>,
+    /// Frames after the last commit frame, written by a transaction that
+    /// never committed. SQLite ignores them.
+    pub uncommitted: Option<&'a [WalFrame]>,
 }
 
 impl WalHeader {
+    /// Checksums sum 32-bit words read in big-endian with `MAGIC_NUMBER_2`
+    /// and in little-endian with `MAGIC_NUMBER_1`, the native byte order of
+    /// the machine that created the WAL
+    pub fn big_endian_checksum(&self) -> bool {
+        self.magic_number == MAGIC_NUMBER_2
+    }
+
     pub fn checksum(&self) -> (u32, u32) {
         let values = [
-            // MAGIC_NUMBER_2 uses big-endian, which we'll assume for now.
-            MAGIC_NUMBER_2,
+            self.magic_number,
             self.file_format,
             self.page_size,
             self.checkpoint_seq,
             self.salt_1,
             self.salt_2,
         ];
-        checksum(&values, None, None)
+        checksum_values(&values, self.big_endian_checksum(), 0, 0)
+    }
+
+    /// Checksum of `frame` chained after `checksum_1` and `checksum_2`, the
+    /// checksum of the previous frame or of the header for the first frame
+    pub fn frame_checksum(&self, frame: &WalFrame, checksum_1: u32, checksum_2: u32) -> (u32, u32) {
+        let big_endian = self.big_endian_checksum();
+        let (checksum_1, checksum_2) = frame.header.checksum(big_endian, checksum_1, checksum_2);
+        checksum_bytes(&frame.data, big_endian, checksum_1, checksum_2)
     }
 }
 
 impl WalFrameHeader {
-    pub fn checksum(&self, checksum_1: u32, checksum_2: u32) -> (u32, u32) {
+    /// Commit frames end a transaction and hold the database size after it
+    pub fn is_commit(&self) -> bool {
+        self.db_size_after_commit != 0
+    }
+
+    pub fn checksum(&self, big_endian: bool, checksum_1: u32, checksum_2: u32) -> (u32, u32) {
         let values = [self.page_number, self.db_size_after_commit];
-        checksum(&values, Some(checksum_1), Some(checksum_2))
+        checksum_values(&values, big_endian, checksum_1, checksum_2)
     }
 }
 
+/// Checksum of `values`, stored as big-endian in the WAL, when the words are
+/// read in the given byte order
+fn checksum_values(values: &[u32], big_endian: bool, s1: u32, s2: u32) -> (u32, u32) {
+    if big_endian {
+        checksum(values, Some(s1), Some(s2))
+    } else {
+        let values: Vec<u32> = values.iter().map(|v| v.swap_bytes()).collect();
+        checksum(&values, Some(s1), Some(s2))
+    }
+}
+
+/// Checksum of `bytes` read as 32-bit words in the given byte order
+pub fn checksum_bytes(bytes: &[u8], big_endian: bool, s1: u32, s2: u32) -> (u32, u32) {
+    let values: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|word| {
+            let word = word.try_into().unwrap();
+            if big_endian {
+                u32::from_be_bytes(word)
+            } else {
+                u32::from_le_bytes(word)
+            }
+        })
+        .collect();
+    checksum(&values, Some(s1), Some(s2))
+}
+
 pub fn checksum(input: &[u32], s1: Option<u32>, s2: Option<u32>) -> (u32, u32) {
-    let mut s1 = if let Some(s1) = s1 { s1 } else { 0u32 };
-    let mut s2 = if let Some(s2) = s2 { s2 } else { 0u32 };
+    let mut s1 = s1.unwrap_or_default();
+    let mut s2 = s2.unwrap_or_default();
 
-    if input.len() * 4 % 8 != 0 {
+    if !input.len().is_multiple_of(2) {
         panic!("input must be a multiple of 8 bytes, given {}", input.len())
     }
 
@@ -1,7 +1,9 @@
+//! Module to manipulate WAL files
+use sqlite_types::{DbHeader, Pager};
+use std::borrow::Cow;
 use std::cmp;
-///! Module to manipulate WAL files
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{self, Write};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -9,52 +11,178 @@ type Error = Box<dyn std::error::Error + Send + Sync>;
 /// Arguments:
 /// - `db`: database to backfill
 /// - `wal`: WAL to apply
+///
+/// Only the committed transactions are applied, like SQLite does: the
+/// frames of a transaction that never committed are ignored. See
+/// [`backfill_frames`] to apply them anyway.
+///
 /// Warning: risks of corruption if used on a live database.
 pub fn backfill(db: &mut sqlite_types::Db, wal: &sqlite_types::Wal) -> Result<(), Error> {
-    if db.header.page_size as u32 != wal.header.page_size {
+    backfill_frames(db, &wal.header, wal.committed_frames())
+}
+
+/// Apply `frames` of the WAL described by `wal_header` to the database,
+/// whether they were committed or not.
+pub fn backfill_frames(
+    db: &mut sqlite_types::Db,
+    wal_header: &sqlite_types::WalHeader,
+    frames: &[sqlite_types::WalFrame],
+) -> Result<(), Error> {
+    if db.header.page_size != wal_header.page_size {
         return Err(format!(
             "Error: page_size mismatch between WAL ({}) and DB ({}).",
-            wal.header.page_size, db.header.page_size
+            wal_header.page_size, db.header.page_size
         )
         .into());
     }
 
-    for frame in &wal.frames {
-        assert_eq!(wal.header.page_size as usize, frame.data.len());
+    for frame in frames {
+        assert_eq!(wal_header.page_size as usize, frame.data.len());
+
+        if frame.header.page_number == 1 {
+            // The first page (page are 1 indexed) is the header
+            db.header = sqlite_decoder::db::decode_header(&frame.data)
+                .map_err(|err| format!("failed to decode database header: {}", err))?;
+        }
+        db.pages
+            .insert(frame.header.page_number, frame.data.clone());
+
+        if frame.header.is_commit() {
+            // The database is truncated or grown to the size after the
+            // commit, for instance after a VACUUM
+            let db_size = frame.header.db_size_after_commit;
+            db.header.db_size = db_size;
+            db.pages.retain(|page_number, _| *page_number <= db_size);
+        }
+    }
 
-        if let Some(page) = db.pages.get_mut(&frame.header.page_number) {
-            if frame.header.page_number == 1 {
-                // The first page (page are 1 indexed) is the header
-                let new_header = sqlite_decoder::db::decode_header(&frame.data).unwrap();
-                db.header = new_header;
+    // Frames after the last commit only grow the database
+    let uncommitted = frames
+        .iter()
+        .rev()
+        .take_while(|frame| !frame.header.is_commit());
+    if let Some(last_page) = uncommitted.map(|frame| frame.header.page_number).max() {
+        db.header.db_size = cmp::max(db.header.db_size, last_page);
+    }
+
+    // SQLite writes the pointer-map pages along with the pages they
+    // describe, but rebuild them if the database grew past the pages we
+    // have.
+    let header = &db.header;
+    if header.has_ptrmap()
+        && (2..=header.db_size).any(|n| header.is_ptrmap_page(n) && !db.pages.contains_key(&n))
+    {
+        rebuild_ptrmap(db)?;
+    }
+
+    Ok(())
+}
+
+/// Rewrite the pointer-map pages of an auto-vacuum database from its
+/// B-trees and freelist.
+pub fn rebuild_ptrmap(db: &mut sqlite_types::Db) -> Result<(), Error> {
+    let entries = sqlite_decoder::ptrmap::compute(db)
+        .map_err(|err| format!("failed to compute pointer-map: {}", err))?;
+
+    for (page_number, page) in sqlite_encoder::ptrmap::encode(&db.header, &entries)? {
+        db.pages.insert(page_number, page);
+    }
+
+    Ok(())
+}
+
+/// Database with a WAL applied on top of it, pages are read from the
+/// committed frames of the WAL when they contain them and from the database
+/// otherwise.
+/// Unlike [`backfill`], neither the database nor the WAL are copied.
+pub struct WalPager<'a, P: ?Sized> {
+    db: &'a P,
+    wal: &'a sqlite_types::Wal,
+    header: DbHeader,
+    /// Index of the latest frame of each page in the WAL
+    frames: HashMap<u32, usize>,
+}
+
+impl<'a, P: Pager + ?Sized> WalPager<'a, P> {
+    pub fn new(db: &'a P, wal: &'a sqlite_types::Wal) -> Result<Self, Error> {
+        if db.header().page_size != wal.header.page_size {
+            return Err(format!(
+                "Error: page_size mismatch between WAL ({}) and DB ({}).",
+                wal.header.page_size,
+                db.header().page_size
+            )
+            .into());
+        }
+
+        let mut frames = HashMap::new();
+        for (i, frame) in wal.committed_frames().iter().enumerate() {
+            if frame.data.len() != wal.header.page_size as usize {
+                return Err(format!(
+                    "frame {} has {} bytes, expected the page size ({})",
+                    i,
+                    frame.data.len(),
+                    wal.header.page_size
+                )
+                .into());
             }
+            frames.insert(frame.header.page_number, i);
+        }
 
-            *page = frame.data.clone();
+        let mut header = if let Some(i) = frames.get(&1) {
+            // The first page (page are 1 indexed) is the header
+            sqlite_decoder::db::decode_header(&wal.frames[*i].data)
+                .map_err(|err| format!("failed to decode database header: {}", err))?
         } else {
-            db.pages
-                .insert(frame.header.page_number, frame.data.clone());
-            db.header.db_size += 1;
+            db.header().clone()
+        };
+        if let Some(db_size) = wal.last_commit_db_size() {
+            header.db_size = db_size;
         }
-    }
 
-    Ok(())
+        Ok(Self {
+            db,
+            wal,
+            header,
+            frames,
+        })
+    }
 }
 
-pub fn hint_db_size(wal: &sqlite_types::Wal) -> Result<usize, Error> {
-    let mut max_page_count = 0u32;
+impl<'a, P: Pager + ?Sized> Pager for WalPager<'a, P> {
+    fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    fn page(&self, page_number: u32) -> io::Result<Option<Cow<'_, [u8]>>> {
+        // Pages past the end were truncated by the last transaction
+        if page_number > self.header.db_size {
+            return Ok(None);
+        }
 
-    for frame in &wal.frames {
-        max_page_count = cmp::min(max_page_count, frame.header.db_size_after_commit);
+        match self.frames.get(&page_number) {
+            Some(i) => Ok(Some(Cow::Borrowed(self.wal.frames[*i].data.as_slice()))),
+            None => self.db.page(page_number),
+        }
     }
 
-    Ok(max_page_count as usize * wal.header.page_size as usize)
+    fn page_count(&self) -> u32 {
+        self.header.db_size
+    }
 }
 
+/// Size in bytes of the database after the last committed transaction of
+/// the WAL, 0 when the WAL doesn't contain any commit
+pub fn hint_db_size(wal: &sqlite_types::Wal) -> Result<usize, Error> {
+    let db_size = wal.last_commit_db_size().unwrap_or(0);
+    Ok(db_size as usize * wal.header.page_size as usize)
+}
+
+/// Same as [`backfill`] on the bytes of a database file
 pub fn backfill_bytes(wal: &sqlite_types::Wal, db_bytes: &mut Vec<u8>) -> Result<(), Error> {
-    let db_header = sqlite_decoder::db::decode_header(&db_bytes)
+    let db_header = sqlite_decoder::db::decode_header(db_bytes)
         .map_err(|err| format!("failed to decode database header: {}", err))?;
 
-    if db_header.page_size as u32 != wal.header.page_size {
+    if db_header.page_size != wal.header.page_size {
         return Err(format!(
             "Error: page_size mismatch between WAL ({}) and DB ({}).",
             wal.header.page_size, db_header.page_size
@@ -62,10 +190,14 @@ pub fn backfill_bytes(wal: &sqlite_types::Wal, db_bytes: &mut Vec<u8>) -> Result
         .into());
     }
 
-    for frame in &wal.frames {
+    for frame in wal.committed_frames() {
         assert_eq!(wal.header.page_size as usize, frame.data.len());
 
-        let db_offset = (frame.header.page_number as usize - 1) * wal.header.page_size as usize;
+        // Page numbers are 1 indexed, the decoder stops at frames of page 0
+        let page_index = (frame.header.page_number as usize)
+            .checked_sub(1)
+            .ok_or("frame of page 0")?;
+        let db_offset = page_index * wal.header.page_size as usize;
         let end = db_offset + wal.header.page_size as usize;
 
         if end > db_bytes.len() {
@@ -77,31 +209,28 @@ pub fn backfill_bytes(wal: &sqlite_types::Wal, db_bytes: &mut Vec<u8>) -> Result
             .write(&frame.data)
             .map_err(|err| format!("failed to write: {}", err))?;
         assert_eq!(wrote, wal.header.page_size as usize);
+
+        if frame.header.is_commit() {
+            // Truncate or grow the database to its size after the commit
+            let db_size = frame.header.db_size_after_commit as usize;
+            db_bytes.resize(db_size * wal.header.page_size as usize, 0);
+        }
     }
 
     Ok(())
 }
 
-/// Turn a WAL into a database
+/// Turn the committed transactions of a WAL into a database
 pub fn to_db(
     db_header: &sqlite_types::DbHeader,
     wal: &sqlite_types::Wal,
 ) -> Result<sqlite_types::Db, Error> {
     let mut pages = HashMap::new();
 
-    // Write first page
-    {
-        let header_bytes =
-            sqlite_encoder::db::encode_header(&db_header).map_err(|err| -> Error {
-                format!("failed to encode database header: {}", err).into()
-            })?;
-        let mut first_page = vec![0u8; db_header.page_size as usize];
-        (&mut first_page[0..100])
-            .write(&header_bytes)
-            .map_err(|err| format!("failed to write header: {}", err))?;
-
-        pages.insert(1, first_page);
-    }
+    // The first page is replaced if the WAL contains it
+    let first_page = sqlite_encoder::builder::empty_first_page(db_header)
+        .map_err(|err| -> Error { format!("failed to encode first page: {}", err).into() })?;
+    pages.insert(1, first_page);
 
     let mut db = sqlite_types::Db {
         header: db_header.clone(),
@@ -126,6 +255,7 @@ pub fn merge(wal1: &mut sqlite_types::Wal, wal2: &sqlite_types::Wal) -> Result<(
 mod tests {
     use super::*;
     use tempfile::NamedTempFile;
+    use test_support::{create_db_and_wal, create_db_bytes};
 
     fn open_db(db: &sqlite_types::Db, f: Box<dyn Fn(rusqlite::Connection)>) {
         let bytes = sqlite_encoder::db::encode(db).unwrap();
@@ -162,6 +292,7 @@ mod tests {
             page_size: 4096,
             file_format_write_version: 2,
             file_format_read_version: 2,
+            reserved_bytes: 0,
             max_embedded_payload_frac: 64,
             min_embedded_payload_frac: 32,
             leaf_payload_frac: 32,
@@ -173,7 +304,7 @@ mod tests {
             schema_format_number: 4,
             default_page_cache_size: 0,
             page_num_largest_root_btree: 0,
-            text_encoding: 1,
+            text_encoding: sqlite_types::TextEncoding::UTF8,
             user_version: 0,
             vaccum_mode: 0,
             app_id: 0,
@@ -197,6 +328,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_rebuilds_ptrmap_pages() {
+        let bytes = create_db_bytes(
+            "
+            pragma page_size = 512;
+            pragma auto_vacuum = full;
+            create table test (id integer primary key, value text);
+            create index test_value on test (value);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 300)
+            insert into test select i, printf('%.*c', i * 3, 'x') from n;
+            ",
+        );
+        let original = sqlite_decoder::db::decode(&bytes).unwrap();
+        let ptrmap_pages: Vec<u32> = (1..=original.header.db_size)
+            .filter(|n| original.header.is_ptrmap_page(*n))
+            .collect();
+        assert!(ptrmap_pages.len() > 1);
+
+        let mut db = sqlite_decoder::db::decode(&bytes).unwrap();
+        for page_number in &ptrmap_pages {
+            db.pages.insert(*page_number, vec![0; 512]);
+        }
+        rebuild_ptrmap(&mut db).unwrap();
+        for page_number in &ptrmap_pages {
+            assert_eq!(db.pages[page_number], original.pages[page_number]);
+        }
+
+        // Backfilling restores the pointer-map pages it doesn't have
+        let mut db = sqlite_decoder::db::decode(&bytes).unwrap();
+        db.pages.remove(&2);
+        let wal = sqlite_types::Wal {
+            header: sqlite_types::WalHeader {
+                magic_number: sqlite_types::MAGIC_NUMBER_2,
+                file_format: sqlite_types::SUPPORTED_FILE_FORMAT,
+                page_size: 512,
+                checkpoint_seq: 0,
+                salt_1: 0,
+                salt_2: 0,
+                checksum_1: 0,
+                checksum_2: 0,
+            },
+            frames: vec![],
+            stop_reason: Default::default(),
+        };
+        backfill(&mut db, &wal).unwrap();
+        assert_eq!(db.pages[&2], original.pages[&2]);
+
+        open_db(
+            &db,
+            Box::new(move |conn| {
+                let result: String = pragma(&conn, "integrity_check");
+                assert_eq!(result, "ok");
+            }),
+        );
+    }
+
+    #[test]
+    fn it_reads_db_through_wal() {
+        let db_bytes = include_bytes!("../test/existing.db3");
+        let pager = sqlite_decoder::pager::BufferPager::new(&db_bytes[..]).unwrap();
+
+        let wal = include_bytes!("../test/create-test-table.wal");
+        let wal = sqlite_decoder::wal::decode(wal).unwrap();
+
+        let wal_pager = WalPager::new(&pager, &wal).unwrap();
+        let schemas = sqlite_table::decode_sqlite_schema(&wal_pager).unwrap();
+        assert!(schemas.contains_key("test"));
+
+        // Same result as backfilling a copy of the database
+        let mut db = sqlite_decoder::db::decode(db_bytes).unwrap();
+        backfill(&mut db, &wal).unwrap();
+        assert_eq!(
+            sqlite_encoder::db::encode(&wal_pager).unwrap(),
+            sqlite_encoder::db::encode(&db).unwrap()
+        );
+    }
+
     #[test]
     fn it_applies_wal_on_top_of_db() {
         let db = include_bytes!("../test/existing.db3");
@@ -262,7 +470,7 @@ mod tests {
                     assert_eq!(count, 65);
 
                     let page_count: usize = pragma(&conn, "page_count");
-                    assert_eq!(page_count, 19);
+                    assert_eq!(page_count, 18);
                 }),
             );
         }
@@ -303,4 +511,133 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn it_ignores_uncommitted_transactions() {
+        let (db_bytes, wal_bytes) = create_db_and_wal(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            ",
+            &["
+            insert into test values ('a');
+            insert into test values (printf('%.*c', 2000, 'x'));
+            "],
+        );
+        let mut wal = sqlite_decoder::wal::decode(&wal_bytes).unwrap();
+        assert_eq!(wal.transactions().committed.len(), 2);
+
+        // Tear the last transaction by dropping its commit frame
+        wal.frames.pop();
+        let transactions = wal.transactions();
+        assert_eq!(transactions.committed.len(), 1);
+        let uncommitted = transactions.uncommitted.unwrap();
+        assert!(!uncommitted.is_empty());
+        assert!(uncommitted.iter().all(|frame| !frame.header.is_commit()));
+        assert_eq!(
+            wal.committed_frames().len(),
+            transactions.committed[0].len()
+        );
+
+        let mut db = sqlite_decoder::db::decode(&db_bytes).unwrap();
+        backfill(&mut db, &wal).unwrap();
+
+        let pager = sqlite_decoder::pager::BufferPager::new(&db_bytes[..]).unwrap();
+        let wal_pager = WalPager::new(&pager, &wal).unwrap();
+        assert_eq!(
+            sqlite_encoder::db::encode(&wal_pager).unwrap(),
+            sqlite_encoder::db::encode(&db).unwrap()
+        );
+
+        open_db(
+            &db,
+            Box::new(move |conn| {
+                let result: String = pragma(&conn, "integrity_check");
+                assert_eq!(result, "ok");
+                let count: usize = conn
+                    .query_row("select count(*) from test", [], |row| row.get(0))
+                    .unwrap();
+                assert_eq!(count, 1);
+            }),
+        );
+    }
+
+    /// Backfill in all the ways, check they agree and return the database
+    fn backfill_all(db_bytes: &[u8], wal: &sqlite_types::Wal) -> sqlite_types::Db {
+        let mut db = sqlite_decoder::db::decode(db_bytes).unwrap();
+        backfill(&mut db, wal).unwrap();
+        let encoded = sqlite_encoder::db::encode(&db).unwrap();
+        assert_eq!(db.pages.len(), db.header.db_size as usize);
+
+        let mut bytes = db_bytes.to_vec();
+        backfill_bytes(wal, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), hint_db_size(wal).unwrap());
+        assert_eq!(bytes, encoded);
+
+        let pager = sqlite_decoder::pager::BufferPager::new(db_bytes).unwrap();
+        let wal_pager = WalPager::new(&pager, wal).unwrap();
+        assert_eq!(sqlite_encoder::db::encode(&wal_pager).unwrap(), encoded);
+
+        db
+    }
+
+    #[test]
+    fn it_truncates_database_to_last_commit() {
+        let (db_bytes, wal_bytes) = create_db_and_wal(
+            "
+            pragma page_size = 512;
+            create table test (value text);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 50)
+            insert into test select printf('%.*c', 500, 'x') from n;
+            ",
+            &["
+            drop table test;
+            vacuum;
+            "],
+        );
+        let wal = sqlite_decoder::wal::decode(&wal_bytes).unwrap();
+        let db_size = wal.last_commit_db_size().unwrap();
+        assert!((db_size as usize) < db_bytes.len() / 512);
+
+        let db = backfill_all(&db_bytes, &wal);
+        assert_eq!(db.header.db_size, db_size);
+
+        open_db(
+            &db,
+            Box::new(move |conn| {
+                let result: String = pragma(&conn, "integrity_check");
+                assert_eq!(result, "ok");
+                assert!(!table_list(&conn).contains(&"test".to_owned()));
+            }),
+        );
+    }
+
+    #[test]
+    fn it_grows_database_to_last_commit() {
+        let (db_bytes, wal_bytes) = create_db_and_wal(
+            "pragma page_size = 512;",
+            &["
+            create table test (value text);
+            insert into test values (printf('%.*c', 2000, 'x'));
+            "],
+        );
+        let wal = sqlite_decoder::wal::decode(&wal_bytes).unwrap();
+        let db_size = wal.last_commit_db_size().unwrap();
+        assert!(db_size as usize > db_bytes.len() / 512);
+
+        let db = backfill_all(&db_bytes, &wal);
+        assert_eq!(db.header.db_size, db_size);
+
+        open_db(
+            &db,
+            Box::new(move |conn| {
+                let result: String = pragma(&conn, "integrity_check");
+                assert_eq!(result, "ok");
+                let count: usize = conn
+                    .query_row("select count(*) from test", [], |row| row.get(0))
+                    .unwrap();
+                assert_eq!(count, 1);
+            }),
+        );
+    }
 }
@@ -0,0 +1,97 @@
+//! Databases and WALs created by SQLite for the tests of the workspace
+use rusqlite::Connection;
+use sqlite_types::Db;
+use tempfile::NamedTempFile;
+
+/// Run `sql` on a new database file, the connection is closed before
+/// returning
+pub fn create_db_file(sql: &str) -> NamedTempFile {
+    let file = NamedTempFile::new().unwrap();
+    {
+        let conn = Connection::open(file.path()).unwrap();
+        conn.execute_batch(sql).unwrap();
+    }
+    file
+}
+
+/// Bytes of the database created by `sql`
+pub fn create_db_bytes(sql: &str) -> Vec<u8> {
+    let file = create_db_file(sql);
+    std::fs::read(file.path()).unwrap()
+}
+
+/// Bytes of the database created by `sql`, with `reserved_bytes` bytes
+/// reserved at the end of each page. The database is vacuumed after `sql`
+/// since SQLite only applies them to new pages.
+pub fn create_db_bytes_with_reserved_bytes(sql: &str, reserved_bytes: i32) -> Vec<u8> {
+    let file = NamedTempFile::new().unwrap();
+    {
+        let conn = Connection::open(file.path()).unwrap();
+        let mut reserved_bytes = reserved_bytes;
+        let rc = unsafe {
+            rusqlite::ffi::sqlite3_file_control(
+                conn.handle(),
+                c"main".as_ptr(),
+                rusqlite::ffi::SQLITE_FCNTL_RESERVE_BYTES,
+                &mut reserved_bytes as *mut i32 as *mut _,
+            )
+        };
+        assert_eq!(rc, rusqlite::ffi::SQLITE_OK);
+        conn.execute_batch(sql).unwrap();
+        conn.execute_batch("vacuum;").unwrap();
+    }
+    std::fs::read(file.path()).unwrap()
+}
+
+/// Database created by `sql`, decoded
+pub fn create_db(sql: &str) -> Db {
+    sqlite_decoder::db::decode(&create_db_bytes(sql)).unwrap()
+}
+
+/// Database created by `sql`, decoded, with the connection still open on
+/// its file
+pub fn create_db_and_connection(sql: &str) -> (Db, Connection, NamedTempFile) {
+    let file = NamedTempFile::new().unwrap();
+    let conn = Connection::open(file.path()).unwrap();
+    conn.execute_batch(sql).unwrap();
+
+    let bytes = std::fs::read(file.path()).unwrap();
+    let db = sqlite_decoder::db::decode(&bytes).unwrap();
+    (db, conn, file)
+}
+
+/// Write the database and check it with SQLite, returns the connection
+pub fn check_db(db: &Db) -> (Connection, NamedTempFile) {
+    let file = NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), sqlite_encoder::db::encode(db).unwrap()).unwrap();
+
+    let conn = Connection::open(file.path()).unwrap();
+    let check: String = conn
+        .query_row("pragma integrity_check", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(check, "ok");
+    (conn, file)
+}
+
+/// Run `sql`, then each batch of `wal_sql` in WAL mode. Returns the
+/// database and the WAL as they are after the last batch, before the WAL is
+/// checkpointed.
+pub fn create_db_and_wal(sql: &str, wal_sql: &[&str]) -> (Vec<u8>, Vec<u8>) {
+    let file = NamedTempFile::new().unwrap();
+    let conn = Connection::open(file.path()).unwrap();
+    conn.execute_batch(sql).unwrap();
+    conn.execute_batch(
+        "
+        pragma journal_mode = wal;
+        pragma wal_autocheckpoint = 0;
+        ",
+    )
+    .unwrap();
+    for sql in wal_sql {
+        conn.execute_batch(sql).unwrap();
+    }
+
+    let db_bytes = std::fs::read(file.path()).unwrap();
+    let wal_bytes = std::fs::read(format!("{}-wal", file.path().display())).unwrap();
+    (db_bytes, wal_bytes)
+}